@@ -6,7 +6,7 @@ use std::time::Instant;
 
 use chunkfs::base::HashMapBase;
 use chunkfs::chunkers::{LeapChunker, RabinChunker};
-use chunkfs::hashers::Sha256Hasher;
+use chunkfs::hashers::{Sha256Hasher, XxHasher};
 use chunkfs::Chunker;
 use chunkfs::FileSystem;
 use chunkfs::Hasher;
@@ -17,7 +17,9 @@ fn main() -> io::Result<()> {
     println!();
     //parametrized_write(LeapChunker::default(), SimpleHasher)?;
     //parametrized_write(LeapChunker::default(), Sha256Hasher::default())?;
-    parametrized_write(RabinChunker::new(), Sha256Hasher::default())
+    parametrized_write(RabinChunker::new(), Sha256Hasher::default())?;
+    println!();
+    parametrized_write(RabinChunker::new(), XxHasher)
 }
 
 const MB: usize = 1024 * 1024;
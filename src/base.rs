@@ -1,17 +1,56 @@
 use std::collections::HashMap;
+#[cfg(feature = "mmap")]
+use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::ErrorKind;
+#[cfg(feature = "mmap")]
+use std::path::Path;
 
-use crate::{ChunkHash, Database, Segment};
+#[cfg(feature = "mmap")]
+use memmap2::MmapMut;
+
+use crate::{ChunkHash, Database, IterableDatabase, Segment};
+#[cfg(feature = "mmap")]
+use crate::{Hasher, SEG_SIZE};
 
 /// Simple in-memory hashmap-based storage.
 #[derive(Default)]
 pub struct HashMapBase<Hash: ChunkHash> {
     segment_map: HashMap<Hash, Vec<u8>>, // hashmap<Hash, RefCell<Vec<u8>> for referencing
+    detect_collisions: bool,
+}
+
+impl<Hash: ChunkHash> HashMapBase<Hash> {
+    /// Creates a storage that compares the data of a saved segment with the data already stored under its hash.
+    ///
+    /// If they differ, which means that the hasher produced a collision, saving fails with `ErrorKind::InvalidData`
+    /// and none of the given segments are saved. Otherwise, the segment that was stored first is silently kept.
+    pub fn with_collision_detection() -> Self {
+        Self {
+            segment_map: HashMap::new(),
+            detect_collisions: true,
+        }
+    }
 }
 
 impl<Hash: ChunkHash> Database<Hash> for HashMapBase<Hash> {
     fn save(&mut self, segments: Vec<Segment<Hash>>) -> io::Result<()> {
+        if self.detect_collisions {
+            let mut saved = HashMap::new();
+            for segment in &segments {
+                let stored = self
+                    .segment_map
+                    .get(&segment.hash)
+                    .or_else(|| saved.insert(&segment.hash, &segment.data));
+                if stored.is_some_and(|data| *data != segment.data) {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        "different data has the same hash",
+                    ));
+                }
+            }
+        }
+
         for segment in segments {
             self.segment_map.entry(segment.hash).or_insert(segment.data);
         }
@@ -30,4 +69,955 @@ impl<Hash: ChunkHash> Database<Hash> for HashMapBase<Hash> {
             })
             .collect()
     }
+
+    fn retrieve_partial(&self, request: &[Hash]) -> io::Result<Vec<Option<Vec<u8>>>> {
+        Ok(request
+            .iter()
+            .map(|hash| self.segment_map.get(hash).cloned())
+            .collect())
+    }
+
+    fn contains(&self, hash: &Hash) -> bool {
+        self.segment_map.contains_key(hash)
+    }
+
+    fn contains_is_cheap(&self) -> bool {
+        true
+    }
+
+    fn len(&self) -> Option<usize> {
+        Some(self.segment_map.len())
+    }
+
+    fn stored_size(&self) -> Option<usize> {
+        Some(self.segment_map.values().map(Vec::len).sum())
+    }
+}
+
+impl<Hash: ChunkHash> IterableDatabase<Hash> for HashMapBase<Hash> {
+    fn iterator(&self) -> Box<dyn Iterator<Item = (&Hash, &[u8])> + '_> {
+        Box::new(
+            self.segment_map
+                .iter()
+                .map(|(hash, data)| (hash, data.as_slice())),
+        )
+    }
+}
+
+/// In-memory storage that doesn't deduplicate the data: every saved segment is kept,
+/// even if a segment with the same hash is already stored.
+///
+/// Serves as a baseline for measuring the effect of deduplication.
+#[derive(Default)]
+pub struct NoDedupBase<Hash: ChunkHash> {
+    segments: Vec<(Hash, Vec<u8>)>,
+    index: HashMap<Hash, usize>,
+}
+
+impl<Hash: ChunkHash> Database<Hash> for NoDedupBase<Hash> {
+    fn save(&mut self, segments: Vec<Segment<Hash>>) -> io::Result<()> {
+        for segment in segments {
+            self.index
+                .entry(segment.hash.clone())
+                .or_insert(self.segments.len());
+            self.segments.push((segment.hash, segment.data));
+        }
+        Ok(())
+    }
+
+    fn retrieve(&self, request: Vec<Hash>) -> io::Result<Vec<Vec<u8>>> {
+        request
+            .into_iter()
+            .map(|hash| {
+                self.index
+                    .get(&hash)
+                    .map(|&position| self.segments[position].1.clone())
+                    .ok_or(ErrorKind::NotFound.into())
+            })
+            .collect()
+    }
+
+    fn retrieve_partial(&self, request: &[Hash]) -> io::Result<Vec<Option<Vec<u8>>>> {
+        Ok(request
+            .iter()
+            .map(|hash| {
+                self.index
+                    .get(hash)
+                    .map(|&position| self.segments[position].1.clone())
+            })
+            .collect())
+    }
+
+    fn contains(&self, hash: &Hash) -> bool {
+        self.index.contains_key(hash)
+    }
+
+    fn contains_is_cheap(&self) -> bool {
+        true
+    }
+
+    fn len(&self) -> Option<usize> {
+        Some(self.segments.len())
+    }
+
+    fn stored_size(&self) -> Option<usize> {
+        Some(self.segments.iter().map(|(_, data)| data.len()).sum())
+    }
+}
+
+impl<Hash: ChunkHash> IterableDatabase<Hash> for NoDedupBase<Hash> {
+    fn iterator(&self) -> Box<dyn Iterator<Item = (&Hash, &[u8])> + '_> {
+        Box::new(
+            self.segments
+                .iter()
+                .map(|(hash, data)| (hash, data.as_slice())),
+        )
+    }
+}
+
+/// Wrapper around another database that compresses the data of every segment with zstd before saving it,
+/// and decompresses it on retrieval.
+///
+/// Hashes are left as they are, so deduplication works the same way as with the inner database.
+#[cfg(feature = "compression")]
+pub struct CompressedBase<B> {
+    inner: B,
+    level: i32,
+}
+
+#[cfg(feature = "compression")]
+impl<B> CompressedBase<B> {
+    /// Wraps the given database, compressing segments with the default zstd level.
+    pub fn new(inner: B) -> Self {
+        Self::with_level(inner, zstd::DEFAULT_COMPRESSION_LEVEL)
+    }
+
+    /// Wraps the given database, compressing segments with the given zstd level.
+    pub fn with_level(inner: B, level: i32) -> Self {
+        Self { inner, level }
+    }
+
+    /// Returns a reference to the inner database, which holds compressed data.
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// Unwraps the inner database.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    /// Decompresses the data of a stored segment. Data that can't be decompressed is reported as `ErrorKind::InvalidData`.
+    fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+        zstd::stream::decode_all(data).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(feature = "compression")]
+impl<B: Database<Hash>, Hash: ChunkHash> Database<Hash> for CompressedBase<B> {
+    fn save(&mut self, segments: Vec<Segment<Hash>>) -> io::Result<()> {
+        let segments = segments
+            .into_iter()
+            .map(|segment| {
+                let data = zstd::bulk::compress(&segment.data, self.level)?;
+                Ok(Segment::new(segment.hash, data))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        self.inner.save(segments)
+    }
+
+    fn retrieve(&self, request: Vec<Hash>) -> io::Result<Vec<Vec<u8>>> {
+        self.inner
+            .retrieve(request)?
+            .iter()
+            .map(|data| Self::decompress(data))
+            .collect()
+    }
+
+    fn retrieve_partial(&self, request: &[Hash]) -> io::Result<Vec<Option<Vec<u8>>>> {
+        self.inner
+            .retrieve_partial(request)?
+            .into_iter()
+            .map(|data| data.map(|data| Self::decompress(&data)).transpose())
+            .collect()
+    }
+
+    fn contains(&self, hash: &Hash) -> bool {
+        self.inner.contains(hash)
+    }
+
+    fn contains_is_cheap(&self) -> bool {
+        self.inner.contains_is_cheap()
+    }
+
+    fn len(&self) -> Option<usize> {
+        self.inner.len()
+    }
+
+    fn stored_size(&self) -> Option<usize> {
+        self.inner.stored_size()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.inner.sync()
+    }
+}
+
+/// Database that saves segments to two databases at once, e.g. to populate a new one while migrating to it.
+///
+/// Data is read from the primary database only. If verification is enabled with [`verified`][TeeBase::verified],
+/// it is also read from the secondary one, and `ErrorKind::InvalidData` is returned if they don't match.
+///
+/// Saving is not atomic: segments are saved to the primary database first, so if saving to the secondary one fails,
+/// the primary database keeps them and the error says that only the secondary database is missing them.
+pub struct TeeBase<A, B> {
+    primary: A,
+    secondary: B,
+    verify: bool,
+}
+
+impl<A, B> TeeBase<A, B> {
+    /// Creates a database that saves segments to both `primary` and `secondary` databases, reading from `primary`.
+    pub fn new(primary: A, secondary: B) -> Self {
+        Self {
+            primary,
+            secondary,
+            verify: false,
+        }
+    }
+
+    /// Creates a database like [`new`][TeeBase::new], but checks the retrieved data against the `secondary` database.
+    pub fn verified(primary: A, secondary: B) -> Self {
+        Self {
+            primary,
+            secondary,
+            verify: true,
+        }
+    }
+
+    /// Returns a reference to the primary database.
+    pub fn primary(&self) -> &A {
+        &self.primary
+    }
+
+    /// Returns a reference to the secondary database.
+    pub fn secondary(&self) -> &B {
+        &self.secondary
+    }
+
+    /// Unwraps the primary and the secondary databases.
+    pub fn into_inner(self) -> (A, B) {
+        (self.primary, self.secondary)
+    }
+}
+
+impl<A, B, Hash> Database<Hash> for TeeBase<A, B>
+where
+    A: Database<Hash>,
+    B: Database<Hash>,
+    Hash: ChunkHash,
+{
+    fn save(&mut self, segments: Vec<Segment<Hash>>) -> io::Result<()> {
+        self.primary.save(segments.clone())?;
+        self.secondary.save(segments).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("saved to the primary database, but not to the secondary one: {e}"),
+            )
+        })
+    }
+
+    fn retrieve(&self, request: Vec<Hash>) -> io::Result<Vec<Vec<u8>>> {
+        if !self.verify {
+            return self.primary.retrieve(request);
+        }
+
+        let data = self.primary.retrieve(request.clone())?;
+        if self.secondary.retrieve(request)? != data {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "secondary database holds different data",
+            ));
+        }
+        Ok(data)
+    }
+
+    fn contains(&self, hash: &Hash) -> bool {
+        self.primary.contains(hash)
+    }
+
+    fn contains_is_cheap(&self) -> bool {
+        self.primary.contains_is_cheap()
+    }
+
+    fn len(&self) -> Option<usize> {
+        self.primary.len()
+    }
+
+    fn stored_size(&self) -> Option<usize> {
+        self.primary.stored_size()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.secondary.sync()?;
+        self.primary.sync()
+    }
+}
+
+impl<A, B, Hash> IterableDatabase<Hash> for TeeBase<A, B>
+where
+    A: IterableDatabase<Hash>,
+    B: Database<Hash>,
+    Hash: ChunkHash,
+{
+    fn iterator(&self) -> Box<dyn Iterator<Item = (&Hash, &[u8])> + '_> {
+        self.primary.iterator()
+    }
+}
+
+/// Storage that keeps the data in a memory-mapped file.
+///
+/// Only offsets and lengths of the stored segments are kept in memory,
+/// the data itself is read directly from the mapped region.
+/// The backing file grows as new data is saved.
+///
+/// Each segment is stored as a record prefixed with its length, so the index
+/// can be rebuilt from the file using [`rebuild_index`][MmapBase::rebuild_index].
+#[cfg(feature = "mmap")]
+pub struct MmapBase<Hash: ChunkHash> {
+    file: File,
+    mmap: MmapMut,
+    segment_map: HashMap<Hash, DataInfo>,
+    used_size: usize,
+}
+
+/// Marks the length header of a stored record. A zero header marks the end of stored data.
+#[cfg(feature = "mmap")]
+const RECORD_FLAG: u64 = 1 << 63;
+
+/// Marks a record that was removed and is skipped when the index is rebuilt.
+#[cfg(feature = "mmap")]
+const REMOVED_FLAG: u64 = 1 << 62;
+
+/// Extracts the record length from its header.
+#[cfg(feature = "mmap")]
+const LENGTH_MASK: u64 = !(RECORD_FLAG | REMOVED_FLAG);
+
+/// Size of the length header preceding each record.
+#[cfg(feature = "mmap")]
+const HEADER_SIZE: usize = size_of::<u64>();
+
+/// Location of a segment in the mapped file.
+#[cfg(feature = "mmap")]
+#[derive(Debug, Clone, Copy)]
+struct DataInfo {
+    offset: usize,
+    length: usize,
+}
+
+#[cfg(feature = "mmap")]
+impl<Hash: ChunkHash> MmapBase<Hash> {
+    /// Creates a storage backed by the file at the given `path`.
+    /// If the file already exists, it is truncated.
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(SEG_SIZE as u64)?;
+
+        // SAFETY: the file is owned by the storage and is only modified through the mapping.
+        let mmap = unsafe { MmapMut::map_mut(&file)? };
+
+        Ok(Self {
+            file,
+            mmap,
+            segment_map: HashMap::new(),
+            used_size: 0,
+        })
+    }
+
+    /// Opens a storage from an existing file at the given `path`, rebuilding its index
+    /// by re-hashing the stored data with the given `hasher`.
+    ///
+    /// The hasher must be the same one that was used when the data was saved.
+    pub fn open<P, H>(path: P, hasher: &mut H) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+        H: Hasher<Hash = Hash>,
+    {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            file.set_len(SEG_SIZE as u64)?;
+        }
+
+        // SAFETY: same as in `new`.
+        let mmap = unsafe { MmapMut::map_mut(&file)? };
+
+        let mut base = Self {
+            file,
+            mmap,
+            segment_map: HashMap::new(),
+            used_size: 0,
+        };
+        base.rebuild_index(hasher)?;
+        Ok(base)
+    }
+
+    /// Scans the backing file and reconstructs the in-memory index,
+    /// computing the key of each stored record with the given `hasher`.
+    ///
+    /// Returns `ErrorKind::InvalidData` if a record header is malformed.
+    pub fn rebuild_index<H: Hasher<Hash = Hash>>(&mut self, hasher: &mut H) -> io::Result<()> {
+        self.segment_map.clear();
+
+        let mut offset = 0;
+        while offset + HEADER_SIZE <= self.mmap.len() {
+            let header = self.read_header(offset);
+            if header == 0 {
+                break;
+            }
+
+            let length = (header & LENGTH_MASK) as usize;
+            let data_offset = offset + HEADER_SIZE;
+            if header & RECORD_FLAG == 0 || data_offset + length > self.mmap.len() {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("malformed record header at offset {offset}"),
+                ));
+            }
+
+            if header & REMOVED_FLAG == 0 {
+                let hash = hasher.hash(&self.mmap[data_offset..data_offset + length]);
+                self.segment_map.insert(
+                    hash,
+                    DataInfo {
+                        offset: data_offset,
+                        length,
+                    },
+                );
+            }
+            offset = data_offset + length;
+        }
+
+        self.used_size = offset;
+        Ok(())
+    }
+
+    /// Returns the amount of bytes taken by the records in the backing file,
+    /// including their headers and the removed records until [`compact`][MmapBase::compact] is called.
+    ///
+    /// Unlike it, [`stored_size`][Database::stored_size] counts only the data of the records that are not removed.
+    pub fn used_size(&self) -> usize {
+        self.used_size
+    }
+
+    /// Returns the offset in the backing file at which the data with the given hash is stored.
+    pub fn offset_of(&self, hash: &Hash) -> Option<usize> {
+        self.segment_map.get(hash).map(|info| info.offset)
+    }
+
+    /// Removes the data with the given hash. Returns `false` if there was no such data.
+    ///
+    /// The space taken by the data is not reclaimed until [`compact`][MmapBase::compact] is called.
+    pub fn remove(&mut self, hash: &Hash) -> bool {
+        match self.segment_map.remove(hash) {
+            None => false,
+            Some(info) => {
+                let header_offset = info.offset - HEADER_SIZE;
+                let header = self.read_header(header_offset) | REMOVED_FLAG;
+                self.write_header(header_offset, header);
+                true
+            }
+        }
+    }
+
+    /// Removes all data from the storage.
+    ///
+    /// The backing file keeps its size until [`compact`][MmapBase::compact] is called.
+    pub fn clear(&mut self) {
+        self.segment_map.clear();
+        self.used_size = 0;
+        self.write_header(0, 0);
+    }
+
+    /// Rewrites all stored records contiguously from the start of the backing file,
+    /// reclaiming the space taken by removed data, and shrinks the file accordingly.
+    pub fn compact(&mut self) -> io::Result<()> {
+        let mut infos = self.segment_map.values_mut().collect::<Vec<_>>();
+        infos.sort_by_key(|info| info.offset);
+
+        let mut write_offset = 0;
+        for info in infos {
+            let record_start = info.offset - HEADER_SIZE;
+            let record_length = HEADER_SIZE + info.length;
+            self.mmap
+                .copy_within(record_start..record_start + record_length, write_offset);
+            info.offset = write_offset + HEADER_SIZE;
+            write_offset += record_length;
+        }
+        self.used_size = write_offset;
+
+        let new_size = (self.used_size + HEADER_SIZE).max(SEG_SIZE);
+        if new_size < self.mmap.len() {
+            self.mmap.flush()?;
+            self.file.set_len(new_size as u64)?;
+            // SAFETY: same as in `new`.
+            self.mmap = unsafe { MmapMut::map_mut(&self.file)? };
+        }
+        self.write_header(self.used_size, 0);
+
+        Ok(())
+    }
+
+    fn read_header(&self, offset: usize) -> u64 {
+        u64::from_le_bytes(
+            self.mmap[offset..offset + HEADER_SIZE]
+                .try_into()
+                .expect("header slice has the size of u64"),
+        )
+    }
+
+    fn write_header(&mut self, offset: usize, header: u64) {
+        self.mmap[offset..offset + HEADER_SIZE].copy_from_slice(&header.to_le_bytes());
+    }
+
+    /// Grows the backing file and remaps it, so that at least `required` bytes fit into it.
+    fn reserve(&mut self, required: usize) -> io::Result<()> {
+        if self.used_size + required <= self.mmap.len() {
+            return Ok(());
+        }
+
+        let mut new_size = self.mmap.len().max(SEG_SIZE);
+        while self.used_size + required > new_size {
+            new_size *= 2;
+        }
+
+        self.mmap.flush()?;
+        self.file.set_len(new_size as u64)?;
+        // SAFETY: same as in `new`, the old mapping is dropped right after the new one is created.
+        self.mmap = unsafe { MmapMut::map_mut(&self.file)? };
+        Ok(())
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl<Hash: ChunkHash> Database<Hash> for MmapBase<Hash> {
+    fn save(&mut self, segments: Vec<Segment<Hash>>) -> io::Result<()> {
+        for segment in segments {
+            if self.segment_map.contains_key(&segment.hash) {
+                continue;
+            }
+
+            // additional header is reserved to mark the end of the stored data
+            self.reserve(HEADER_SIZE + segment.data.len() + HEADER_SIZE)?;
+
+            self.write_header(self.used_size, segment.data.len() as u64 | RECORD_FLAG);
+
+            let info = DataInfo {
+                offset: self.used_size + HEADER_SIZE,
+                length: segment.data.len(),
+            };
+            self.mmap[info.offset..info.offset + info.length].copy_from_slice(&segment.data);
+            self.used_size = info.offset + info.length;
+            self.write_header(self.used_size, 0);
+            self.segment_map.insert(segment.hash, info);
+        }
+        Ok(())
+    }
+
+    fn retrieve(&self, request: Vec<Hash>) -> io::Result<Vec<Vec<u8>>> {
+        request
+            .into_iter()
+            .map(|hash| {
+                self.segment_map
+                    .get(&hash)
+                    .map(|info| self.mmap[info.offset..info.offset + info.length].to_vec())
+                    .ok_or(ErrorKind::NotFound.into())
+            })
+            .collect()
+    }
+
+    fn retrieve_partial(&self, request: &[Hash]) -> io::Result<Vec<Option<Vec<u8>>>> {
+        Ok(request
+            .iter()
+            .map(|hash| {
+                self.segment_map
+                    .get(hash)
+                    .map(|info| self.mmap[info.offset..info.offset + info.length].to_vec())
+            })
+            .collect())
+    }
+
+    fn contains(&self, hash: &Hash) -> bool {
+        self.segment_map.contains_key(hash)
+    }
+
+    fn contains_is_cheap(&self) -> bool {
+        true
+    }
+
+    fn len(&self) -> Option<usize> {
+        Some(self.segment_map.len())
+    }
+
+    fn stored_size(&self) -> Option<usize> {
+        Some(self.segment_map.values().map(|info| info.length).sum())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.mmap.flush()?;
+        self.file.sync_all()
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl<Hash: ChunkHash> IterableDatabase<Hash> for MmapBase<Hash> {
+    fn iterator(&self) -> Box<dyn Iterator<Item = (&Hash, &[u8])> + '_> {
+        Box::new(
+            self.segment_map
+                .iter()
+                .map(|(hash, info)| (hash, &self.mmap[info.offset..info.offset + info.length])),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::base::{CompressedBase, HashMapBase, MmapBase, NoDedupBase, TeeBase};
+    use crate::hashers::SimpleHasher;
+    use crate::{Database, Hasher, IterableDatabase, Segment};
+
+    #[test]
+    fn contains_is_cheap_for_map_backed_bases() {
+        let base = HashMapBase::<Vec<u8>>::default();
+        assert!(base.contains_is_cheap());
+
+        let path = std::env::temp_dir().join("chunkfs_contains_is_cheap_test");
+        let base = MmapBase::<Vec<u8>>::new(&path).unwrap();
+        assert!(base.contains_is_cheap());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn contains_finds_saved_segments() {
+        let path = std::env::temp_dir().join("chunkfs_contains_test");
+        let mut mmap_base = MmapBase::new(&path).unwrap();
+        let mut map_base = HashMapBase::default();
+
+        for base in [&mut mmap_base as &mut dyn Database<Vec<u8>>, &mut map_base] {
+            base.save(vec![Segment::new(vec![1], vec![1; 10])]).unwrap();
+            assert!(base.contains(&vec![1]));
+            assert!(!base.contains(&vec![2]));
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn mmap_base_index_is_rebuilt_from_file() {
+        let path = std::env::temp_dir().join("chunkfs_rebuild_index_test");
+        let mut hasher = SimpleHasher;
+
+        let segments = (0..5u8)
+            .map(|i| vec![i; 1000 * (i as usize + 1)])
+            .chain([vec![]])
+            .map(|data| Segment::new(hasher.hash(&data), data))
+            .collect::<Vec<_>>();
+        let hashes = segments
+            .iter()
+            .map(|segment| segment.hash.clone())
+            .collect::<Vec<_>>();
+        let expected = segments
+            .iter()
+            .map(|segment| segment.data.clone())
+            .collect::<Vec<_>>();
+
+        let mut base = MmapBase::new(&path).unwrap();
+        base.save(segments).unwrap();
+        drop(base);
+
+        let mut base = MmapBase::open(&path, &mut hasher).unwrap();
+        assert_eq!(base.retrieve(hashes.clone()).unwrap(), expected);
+
+        base.save(vec![Segment::new(vec![9; 10], vec![9; 10])])
+            .unwrap();
+        base.rebuild_index(&mut hasher).unwrap();
+        assert_eq!(base.retrieve(hashes).unwrap(), expected);
+        assert_eq!(base.retrieve(vec![vec![9; 10]]).unwrap(), vec![vec![9; 10]]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn mmap_base_compaction_reclaims_removed_space() {
+        let path = std::env::temp_dir().join("chunkfs_compaction_test");
+        let mut hasher = SimpleHasher;
+
+        let data = (0..30u8).map(|i| vec![i; 100_000]).collect::<Vec<_>>();
+        let mut base = MmapBase::new(&path).unwrap();
+        base.save(
+            data.iter()
+                .map(|data| Segment::new(data.clone(), data.clone()))
+                .collect(),
+        )
+        .unwrap();
+        let file_size = std::fs::metadata(&path).unwrap().len();
+
+        for removed in data.iter().skip(1) {
+            assert!(base.remove(removed));
+        }
+        assert!(!base.remove(&data[1]));
+        assert_eq!(base.used_size(), 30 * (100_000 + 8));
+
+        base.compact().unwrap();
+        assert_eq!(base.used_size(), 100_000 + 8);
+        assert!(std::fs::metadata(&path).unwrap().len() < file_size);
+        assert_eq!(
+            base.retrieve(vec![data[0].clone()]).unwrap(),
+            vec![data[0].clone()]
+        );
+
+        base.save(vec![Segment::new(data[1].clone(), data[1].clone())])
+            .unwrap();
+        drop(base);
+
+        let base = MmapBase::open(&path, &mut hasher).unwrap();
+        assert_eq!(base.used_size(), 2 * (100_000 + 8));
+        assert_eq!(
+            base.retrieve(vec![data[0].clone(), data[1].clone()])
+                .unwrap(),
+            vec![data[0].clone(), data[1].clone()]
+        );
+        assert!(!base.contains(&data[2]));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn mmap_base_stored_size_excludes_removed_records() {
+        let path = std::env::temp_dir().join("chunkfs_stored_size_test");
+        let mut base = MmapBase::new(&path).unwrap();
+        base.save(vec![
+            Segment::new(vec![1], vec![1; 1000]),
+            Segment::new(vec![2], vec![2; 500]),
+        ])
+        .unwrap();
+        assert_eq!(base.stored_size(), Some(1500));
+
+        assert!(base.remove(&vec![1]));
+        assert_eq!(base.stored_size(), Some(500));
+        assert_eq!(base.used_size(), 1500 + 2 * 8);
+
+        base.compact().unwrap();
+        assert_eq!(base.stored_size(), Some(500));
+        assert_eq!(base.used_size(), 500 + 8);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn mmap_base_can_be_reused_after_clear() {
+        let path = std::env::temp_dir().join("chunkfs_clear_test");
+        let mut hasher = SimpleHasher;
+
+        let mut base = MmapBase::new(&path).unwrap();
+        base.save(vec![Segment::new(vec![1; 500], vec![1; 500])])
+            .unwrap();
+        base.clear();
+        base.save(vec![Segment::new(vec![2; 100], vec![2; 100])])
+            .unwrap();
+        drop(base);
+
+        let base = MmapBase::open(&path, &mut hasher).unwrap();
+        assert!(!base.contains(&vec![1; 500]));
+        assert!(base.contains(&vec![2; 100]));
+        assert_eq!(base.used_size(), 100 + 8);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn compressed_base_returns_original_data() {
+        let mut base = CompressedBase::new(HashMapBase::default());
+        let data = (0..100000).map(|i| (i % 7) as u8).collect::<Vec<_>>();
+        let segments = vec![
+            Segment::new(vec![1], data.clone()),
+            Segment::new(vec![2], vec![]),
+        ];
+        base.save(segments).unwrap();
+
+        let retrieved = base.retrieve(vec![vec![1], vec![2]]).unwrap();
+        assert_eq!(retrieved, vec![data.clone(), vec![]]);
+        assert!(base.contains(&vec![1]));
+
+        let stored = base
+            .inner()
+            .iterator()
+            .map(|(_, data)| data.len())
+            .sum::<usize>();
+        assert!(stored < data.len() / 10);
+    }
+
+    #[test]
+    fn compressed_base_reports_corrupted_data() {
+        let data = (0..100000).map(|i| (i % 7) as u8).collect::<Vec<_>>();
+        let mut compressed = zstd::bulk::compress(&data, 3).unwrap();
+        compressed.truncate(compressed.len() / 2);
+
+        let mut inner = HashMapBase::default();
+        inner.save(vec![Segment::new(vec![1], compressed)]).unwrap();
+        let base = CompressedBase::new(inner);
+
+        let error = base.retrieve(vec![vec![1]]).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        let error = base.retrieve_partial(&[vec![1]]).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn len_counts_stored_segments() {
+        let mut base = HashMapBase::default();
+        assert_eq!(base.is_empty(), Some(true));
+        base.save(vec![
+            Segment::new(vec![1], vec![1]),
+            Segment::new(vec![2], vec![2]),
+            Segment::new(vec![1], vec![1]),
+        ])
+        .unwrap();
+        assert_eq!(base.len(), Some(2));
+        assert_eq!(base.is_empty(), Some(false));
+
+        let path = std::env::temp_dir().join("chunkfs_mmap_len_test");
+        let mut base = MmapBase::new(&path).unwrap();
+        base.save(vec![
+            Segment::new(vec![1], vec![1; 100]),
+            Segment::new(vec![2], vec![2; 100]),
+        ])
+        .unwrap();
+        assert_eq!(base.len(), Some(2));
+        base.remove(&vec![1]);
+        assert_eq!(base.len(), Some(1));
+        base.clear();
+        assert_eq!(base.is_empty(), Some(true));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn retrieve_partial_marks_missing_segments() {
+        let path = std::env::temp_dir().join("chunkfs_retrieve_partial_test");
+        let mut mmap_base = MmapBase::new(&path).unwrap();
+        let mut map_base = HashMapBase::default();
+        let mut no_dedup_base = NoDedupBase::default();
+        let mut compressed_base = CompressedBase::new(HashMapBase::default());
+
+        let request = vec![vec![1], vec![2], vec![3], vec![1]];
+        let expected = vec![
+            Some(vec![1; 10]),
+            None,
+            Some(vec![3; 20]),
+            Some(vec![1; 10]),
+        ];
+        for base in [
+            &mut mmap_base as &mut dyn Database<Vec<u8>>,
+            &mut map_base,
+            &mut no_dedup_base,
+            &mut compressed_base,
+        ] {
+            base.save(vec![
+                Segment::new(vec![1], vec![1; 10]),
+                Segment::new(vec![3], vec![3; 20]),
+            ])
+            .unwrap();
+            assert!(base.retrieve(request.clone()).is_err());
+            assert_eq!(base.retrieve_partial(&request).unwrap(), expected);
+            assert!(base.retrieve_partial(&[]).unwrap().is_empty());
+        }
+        std::fs::remove_file(path).unwrap();
+
+        // data that can't be decompressed is corrupted, not missing
+        let mut inner = HashMapBase::default();
+        inner
+            .save(vec![Segment::new(vec![5], vec![1, 2, 3])])
+            .unwrap();
+        let base = CompressedBase::new(inner);
+        assert_eq!(base.retrieve_partial(&[vec![6]]).unwrap(), vec![None]);
+        let error = base.retrieve_partial(&[vec![5], vec![6]]).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn tee_base_saves_to_both_databases() {
+        let path = std::env::temp_dir().join("chunkfs_tee_base_test");
+        let mut base = TeeBase::verified(HashMapBase::default(), MmapBase::new(&path).unwrap());
+        let mut hasher = SimpleHasher;
+
+        for i in 0..10u8 {
+            let data = vec![i; 100 * (i as usize + 1)];
+            let repeated = vec![0; 100];
+            base.save(vec![
+                Segment::new(hasher.hash(&data), data),
+                Segment::new(hasher.hash(&repeated), repeated),
+            ])
+            .unwrap();
+        }
+
+        let hashes = base
+            .iterator()
+            .map(|(hash, _)| hash.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(hashes.len(), 10);
+        assert_eq!(
+            base.retrieve(hashes.clone()).unwrap(),
+            base.secondary().retrieve(hashes.clone()).unwrap()
+        );
+        assert_eq!(base.secondary().len(), Some(10));
+
+        let (mut primary, secondary) = base.into_inner();
+        primary.save(vec![Segment::new(vec![42], vec![1])]).unwrap();
+        let mut base = TeeBase::verified(primary, secondary);
+        base.secondary
+            .save(vec![Segment::new(vec![43], vec![1])])
+            .unwrap();
+        base.primary
+            .save(vec![Segment::new(vec![43], vec![2])])
+            .unwrap();
+        assert_eq!(
+            base.retrieve(vec![vec![43]]).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+        assert!(base.retrieve(vec![vec![42]]).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Database that fails every save.
+    struct BrokenBase;
+
+    impl Database<Vec<u8>> for BrokenBase {
+        fn save(&mut self, _segments: Vec<Segment<Vec<u8>>>) -> std::io::Result<()> {
+            Err(std::io::ErrorKind::OutOfMemory.into())
+        }
+
+        fn retrieve(&self, _request: Vec<Vec<u8>>) -> std::io::Result<Vec<Vec<u8>>> {
+            Err(std::io::ErrorKind::NotFound.into())
+        }
+    }
+
+    #[test]
+    fn tee_base_saves_to_primary_first() {
+        let segment = || vec![Segment::new(vec![1], vec![1; 10])];
+
+        let mut base = TeeBase::new(BrokenBase, HashMapBase::default());
+        assert!(base.save(segment()).is_err());
+        assert_eq!(base.secondary().len(), Some(0));
+
+        let mut base = TeeBase::new(HashMapBase::default(), BrokenBase);
+        let error = base.save(segment()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::OutOfMemory);
+        assert!(error.to_string().contains("secondary"));
+        assert_eq!(base.retrieve(vec![vec![1]]).unwrap(), vec![vec![1; 10]]);
+    }
 }
@@ -0,0 +1,166 @@
+use std::io;
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+
+use crate::Chunker;
+
+/// Statistics of the chunks produced by a [`Chunker`] on some data.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ChunkStats {
+    pub count: usize,
+    pub avg_size: f64,
+    pub min_size: usize,
+    pub max_size: usize,
+}
+
+/// Expected [`ChunkStats`] along with allowed relative deviation from them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkStatsTolerance {
+    pub expected: ChunkStats,
+    /// Allowed relative deviation, e.g. `0.05` allows every value to differ from the expected one by 5%.
+    pub tolerance: f64,
+}
+
+impl ChunkStats {
+    /// Chunks the whole `data` with the given `chunker` and gathers statistics of the resulting chunks.
+    ///
+    /// The leftover returned by [`remainder`][Chunker::remainder] is counted as the last chunk,
+    /// the same way it is stored when the file is closed.
+    pub fn collect<C: Chunker>(chunker: &mut C, data: &[u8]) -> Self {
+        if data.is_empty() {
+            return Self::default();
+        }
+
+        let empty = Vec::with_capacity(chunker.estimate_chunk_count(data));
+        let mut sizes = chunker
+            .chunk_data(data, empty)
+            .iter()
+            .map(|chunk| chunk.length())
+            .collect::<Vec<_>>();
+        if !chunker.remainder().is_empty() {
+            sizes.push(chunker.remainder().len());
+        }
+
+        Self {
+            count: sizes.len(),
+            avg_size: data.len() as f64 / sizes.len() as f64,
+            min_size: sizes.iter().copied().min().unwrap_or_default(),
+            max_size: sizes.iter().copied().max().unwrap_or_default(),
+        }
+    }
+}
+
+/// Chunks `data` with the given `chunker` and checks that the resulting [`ChunkStats`]
+/// are within tolerance of the expected ones.
+///
+/// Used to lock in the behavior of a chunker, so that an accidental change in the algorithm is caught.
+/// Returns the collected stats, or an error of kind `InvalidData` describing every value that is out of bounds.
+pub fn assert_chunk_stats<C: Chunker>(
+    mut chunker: C,
+    data: &[u8],
+    expected: ChunkStatsTolerance,
+) -> io::Result<ChunkStats> {
+    let actual = ChunkStats::collect(&mut chunker, data);
+    let wanted = expected.expected;
+
+    let values = [
+        ("chunk count", actual.count as f64, wanted.count as f64),
+        ("average chunk size", actual.avg_size, wanted.avg_size),
+        (
+            "minimum chunk size",
+            actual.min_size as f64,
+            wanted.min_size as f64,
+        ),
+        (
+            "maximum chunk size",
+            actual.max_size as f64,
+            wanted.max_size as f64,
+        ),
+    ];
+
+    let mismatches = values
+        .iter()
+        .filter(|(_, actual, wanted)| (actual - wanted).abs() > wanted.abs() * expected.tolerance)
+        .map(|(name, actual, wanted)| format!("{name} is {actual:.2}, expected {wanted:.2}"))
+        .collect::<Vec<_>>();
+
+    if mismatches.is_empty() {
+        Ok(actual)
+    } else {
+        Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "chunk stats are out of tolerance of {:.1}%: {}",
+                expected.tolerance * 100.0,
+                mismatches.join("; ")
+            ),
+        ))
+    }
+}
+
+/// Chunks the whole `data` with the given `chunker` and returns the time spent on chunking
+/// along with the number of chunks produced by [`chunk_data`][Chunker::chunk_data].
+///
+/// The chunker is taken by value, so that no state is left from previous uses.
+/// The leftover returned by [`remainder`][Chunker::remainder] is not counted.
+pub fn benchmark_chunker<C: Chunker>(mut chunker: C, data: &[u8]) -> (Duration, usize) {
+    let empty = Vec::with_capacity(chunker.estimate_chunk_count(data));
+
+    let start = Instant::now();
+    let chunks = chunker.chunk_data(data, empty);
+    (start.elapsed(), chunks.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use crate::bench::{assert_chunk_stats, benchmark_chunker, ChunkStats, ChunkStatsTolerance};
+    use crate::chunkers::{FSChunker, LeapChunker};
+    use crate::Chunker;
+
+    const FS_CHUNKER_STATS: ChunkStats = ChunkStats {
+        count: 3,
+        avg_size: 10000.0 / 3.0,
+        min_size: 1808,
+        max_size: 4096,
+    };
+
+    #[test]
+    fn fs_chunker_stats_are_pinned() {
+        let expected = ChunkStatsTolerance {
+            expected: FS_CHUNKER_STATS,
+            tolerance: 0.0,
+        };
+
+        let stats = assert_chunk_stats(FSChunker::new(4096), &[1; 10000], expected).unwrap();
+        assert_eq!(stats, FS_CHUNKER_STATS);
+    }
+
+    #[test]
+    fn changed_chunker_is_reported() {
+        let expected = ChunkStatsTolerance {
+            expected: FS_CHUNKER_STATS,
+            tolerance: 0.05,
+        };
+
+        let error = assert_chunk_stats(FSChunker::new(2048), &[1; 10000], expected).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error
+            .to_string()
+            .contains("chunk count is 5.00, expected 3.00"));
+    }
+
+    #[test]
+    fn benchmark_counts_same_chunks_as_chunk_data() {
+        let data = (0..1_000_000)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect::<Vec<_>>();
+
+        let mut chunker = LeapChunker::default();
+        let expected = chunker.chunk_data(&data, vec![]).len();
+
+        let (_, count) = benchmark_chunker(LeapChunker::default(), &data);
+        assert_eq!(count, expected);
+    }
+}
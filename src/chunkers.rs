@@ -28,6 +28,20 @@ pub struct RabinChunker {
     params: Option<chunking::rabin::ChunkerParams>,
 }
 
+/// Chunker that cuts the data at the given offsets, regardless of its contents.
+///
+/// Offsets are counted from the beginning of the written data and must be ascending.
+/// Each call to [`chunk_data`][Chunker::chunk_data] is expected to start with the [`remainder`][Chunker::remainder]
+/// of the previous one, as it is done when writing to a file.
+/// Useful for reproducing exact chunk boundaries, e.g. from a trace or another tool.
+#[derive(Debug)]
+pub struct ReplayChunker {
+    cuts: Vec<usize>,
+    next_cut: usize,
+    position: usize,
+    rest: Vec<u8>,
+}
+
 impl RabinChunker {
     pub fn new() -> Self {
         Self {
@@ -37,6 +51,12 @@ impl RabinChunker {
     }
 }
 
+impl Default for RabinChunker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FSChunker {
     pub fn new(chunk_size: usize) -> Self {
         Self {
@@ -55,6 +75,214 @@ impl SuperChunker {
     }
 }
 
+impl Default for SuperChunker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Chunker that cuts the data into chunks of pseudo-random sizes between `min_size` and `max_size`,
+/// regardless of its contents.
+///
+/// Chunk boundaries only depend on the seed and on the position in the written data,
+/// so they are reproducible across runs and don't depend on how the data is split into segments.
+#[derive(Debug)]
+pub struct SeededChunker {
+    state: u64,
+    min_size: usize,
+    max_size: usize,
+    next_length: usize,
+    rest: Vec<u8>,
+}
+
+impl SeededChunker {
+    pub fn new(seed: u64, min_size: usize, max_size: usize) -> Self {
+        assert!(
+            0 < min_size && min_size <= max_size,
+            "chunk sizes must satisfy 0 < min_size <= max_size"
+        );
+
+        let mut chunker = Self {
+            state: seed,
+            min_size,
+            max_size,
+            next_length: 0,
+            rest: vec![],
+        };
+        chunker.next_length = chunker.generate_length();
+        chunker
+    }
+
+    /// Generates the next chunk length using SplitMix64.
+    fn generate_length(&mut self) -> usize {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^= z >> 31;
+
+        let range = (self.max_size - self.min_size + 1) as u64;
+        self.min_size + (z % range) as usize
+    }
+}
+
+/// Chunker that runs the inner chunker and splits every chunk longer than `max_size`
+/// into pieces of `max_size` bytes, so that no chunk exceeds it.
+///
+/// The remainder of the inner chunker is split as well, keeping only the part shorter than `max_size`.
+/// The inner chunker still gets its whole remainder back on the next call, so stateful chunkers can be wrapped too.
+#[derive(Debug)]
+pub struct MaxCapChunker<C: Chunker> {
+    inner: C,
+    max_size: usize,
+    sync: InnerSync,
+    rest: Vec<u8>,
+}
+
+impl<C: Chunker> MaxCapChunker<C> {
+    pub fn new(inner: C, max_size: usize) -> Self {
+        assert!(max_size > 0, "maximum chunk size must be positive");
+        Self {
+            inner,
+            max_size,
+            sync: InnerSync::default(),
+            rest: vec![],
+        }
+    }
+
+    /// Pushes chunks of at most `max_size` bytes covering `length` bytes starting at `offset`.
+    /// Returns the length of the tail that was left, which is shorter than `max_size` if `keep_tail` is set, or 0 otherwise.
+    fn push_capped(
+        &self,
+        chunks: &mut Vec<Chunk>,
+        offset: usize,
+        length: usize,
+        keep_tail: bool,
+    ) -> usize {
+        let mut start = offset;
+        let end = offset + length;
+        while end - start > self.max_size || (!keep_tail && start < end) {
+            let piece = min(self.max_size, end - start);
+            chunks.push(Chunk::new(start, piece));
+            start += piece;
+        }
+        end - start
+    }
+}
+
+/// Keeps the inner chunker of a wrapper in sync with the data.
+///
+/// The inner chunker expects its whole remainder to be given back to it on the next call,
+/// but the wrapper may cut the data elsewhere and only gets back its own remainder.
+/// The difference between the two remainders is kept here.
+#[derive(Debug, Default)]
+struct InnerSync {
+    /// Beginning of the inner remainder that precedes the remainder of the wrapper.
+    ahead: Vec<u8>,
+    /// Length of the beginning of the wrapper remainder that precedes the remainder of the inner chunker.
+    behind: usize,
+    /// Data given to the inner chunker on the last call.
+    input: Vec<u8>,
+    /// Length of `ahead` on the last call.
+    shift: usize,
+    /// Start of the inner remainder in `input`.
+    inner_rest: usize,
+}
+
+impl InnerSync {
+    /// Runs the inner chunker on the data given to the wrapper.
+    ///
+    /// Returns ends of the found chunks and the start of the inner remainder, relative to `data`.
+    /// Positions that precede `data` are returned as 0.
+    fn chunk<C: Chunker>(&mut self, inner: &mut C, data: &[u8]) -> (Vec<usize>, usize) {
+        self.shift = self.ahead.len();
+        self.input = std::mem::take(&mut self.ahead);
+        self.input.extend_from_slice(&data[self.behind..]);
+
+        let ends = inner
+            .chunk_data(&self.input, vec![])
+            .iter()
+            .map(|chunk| self.to_data(chunk.range().end))
+            .collect();
+        self.inner_rest = self.input.len() - inner.remainder().len();
+        (ends, self.to_data(self.inner_rest))
+    }
+
+    /// Records that the wrapper keeps `data[rest_start..]` from the last call as its remainder.
+    fn keep(&mut self, rest_start: usize) {
+        let wrapper_rest = rest_start + self.shift - self.behind;
+        if self.inner_rest < wrapper_rest {
+            self.ahead = self.input[self.inner_rest..wrapper_rest].to_vec();
+            self.behind = 0;
+        } else {
+            self.behind = self.inner_rest - wrapper_rest;
+        }
+        self.input.clear();
+    }
+
+    fn to_data(&self, position: usize) -> usize {
+        (position + self.behind).saturating_sub(self.shift)
+    }
+}
+
+/// Chunker that runs the inner chunker and moves every cut point to the nearest multiple of `block_size`,
+/// so that lengths of all chunks are multiples of it, except for the last chunk of a file.
+///
+/// Lengths of the chunks are kept within `min_size..=max_size`, rounded inwards to multiples of `block_size`:
+/// a cut point that would make a chunk shorter is moved forward, and a chunk that would be longer is split.
+/// Cut points that would make a chunk empty after rounding are dropped, merging the chunk into the next one.
+/// Chunks found by the inner chunker may therefore be merged or shifted, which affects deduplication.
+/// The last chunk of a file is not longer than `max_size`, but may be shorter than `min_size`.
+#[derive(Debug)]
+pub struct BlockAlignedChunker<C: Chunker> {
+    inner: C,
+    block_size: usize,
+    min_size: usize,
+    max_size: usize,
+    sync: InnerSync,
+    /// Cut points of the inner chunker in the remainder that were not reached yet.
+    pending: Vec<usize>,
+    rest: Vec<u8>,
+}
+
+impl<C: Chunker> BlockAlignedChunker<C> {
+    pub fn new(inner: C, block_size: usize, min_size: usize, max_size: usize) -> Self {
+        assert!(block_size > 0, "block size must be positive");
+        let min_size = min_size.div_ceil(block_size) * block_size;
+        let max_size = max_size / block_size * block_size;
+        assert!(
+            0 < max_size && min_size <= max_size,
+            "chunk sizes must allow at least one multiple of the block size"
+        );
+
+        Self {
+            inner,
+            block_size,
+            min_size,
+            max_size,
+            sync: InnerSync::default(),
+            pending: vec![],
+            rest: vec![],
+        }
+    }
+
+    /// Rounds the length to the nearest multiple of `block_size`.
+    fn round(&self, length: usize) -> usize {
+        (length + self.block_size / 2) / self.block_size * self.block_size
+    }
+}
+
+impl ReplayChunker {
+    pub fn new(cuts: Vec<usize>) -> Self {
+        Self {
+            cuts,
+            next_cut: 0,
+            position: 0,
+            rest: vec![],
+        }
+    }
+}
+
 impl Chunker for FSChunker {
     fn chunk_data(&mut self, data: &[u8], empty: Vec<Chunk>) -> Vec<Chunk> {
         let mut offset = 0;
@@ -82,6 +310,10 @@ impl Chunker for FSChunker {
     fn estimate_chunk_count(&self, data: &[u8]) -> usize {
         data.len() / self.chunk_size + 1
     }
+
+    fn name(&self) -> &str {
+        "FSChunker"
+    }
 }
 
 impl Chunker for LeapChunker {
@@ -103,6 +335,10 @@ impl Chunker for LeapChunker {
     fn estimate_chunk_count(&self, data: &[u8]) -> usize {
         data.len() / 1024 * 8
     }
+
+    fn name(&self) -> &str {
+        "LeapChunker"
+    }
 }
 
 impl Chunker for SuperChunker {
@@ -129,6 +365,10 @@ impl Chunker for SuperChunker {
     fn estimate_chunk_count(&self, data: &[u8]) -> usize {
         data.len() / 2048
     }
+
+    fn name(&self) -> &str {
+        "SuperChunker"
+    }
 }
 
 impl Chunker for RabinChunker {
@@ -154,6 +394,154 @@ impl Chunker for RabinChunker {
     fn estimate_chunk_count(&self, data: &[u8]) -> usize {
         data.len() / 16384
     }
+
+    fn name(&self) -> &str {
+        "RabinChunker"
+    }
+}
+
+impl Chunker for ReplayChunker {
+    fn chunk_data(&mut self, data: &[u8], empty: Vec<Chunk>) -> Vec<Chunk> {
+        let mut chunks = empty;
+        let mut start = 0;
+        while let Some(&cut) = self.cuts.get(self.next_cut) {
+            if cut > self.position + data.len() {
+                break;
+            }
+
+            let end = cut.saturating_sub(self.position);
+            if end > start {
+                chunks.push(Chunk::new(start, end - start));
+                start = end;
+            }
+            self.next_cut += 1;
+        }
+
+        self.position += start;
+        self.rest = data[start..].to_vec();
+        chunks
+    }
+
+    fn remainder(&self) -> &[u8] {
+        &self.rest
+    }
+
+    fn estimate_chunk_count(&self, data: &[u8]) -> usize {
+        self.cuts[self.next_cut..].partition_point(|&cut| cut <= self.position + data.len())
+    }
+
+    fn name(&self) -> &str {
+        "ReplayChunker"
+    }
+}
+
+impl<C: Chunker> Chunker for MaxCapChunker<C> {
+    fn chunk_data(&mut self, data: &[u8], empty: Vec<Chunk>) -> Vec<Chunk> {
+        let (ends, inner_rest) = self.sync.chunk(&mut self.inner, data);
+
+        let mut chunks = empty;
+        let mut start = 0;
+        for end in ends {
+            if end > start {
+                self.push_capped(&mut chunks, start, end - start, false);
+                start = end;
+            }
+        }
+
+        let start = start.max(inner_rest);
+        let tail = self.push_capped(&mut chunks, start, data.len() - start, true);
+        let rest_start = data.len() - tail;
+        self.sync.keep(rest_start);
+        self.rest = data[rest_start..].to_vec();
+        chunks
+    }
+
+    fn remainder(&self) -> &[u8] {
+        &self.rest
+    }
+
+    fn estimate_chunk_count(&self, data: &[u8]) -> usize {
+        self.inner.estimate_chunk_count(data)
+    }
+
+    fn name(&self) -> &str {
+        "MaxCapChunker"
+    }
+}
+
+impl<C: Chunker> Chunker for BlockAlignedChunker<C> {
+    fn chunk_data(&mut self, data: &[u8], empty: Vec<Chunk>) -> Vec<Chunk> {
+        let (found, _) = self.sync.chunk(&mut self.inner, data);
+        let mut ends = std::mem::take(&mut self.pending);
+        ends.extend(found);
+
+        let mut chunks = empty;
+        let mut start = 0;
+        let mut next = 0;
+        while let Some(&end) = ends.get(next) {
+            let length = self.round(end.saturating_sub(start));
+            if length == 0 {
+                next += 1;
+                continue;
+            }
+
+            let length = length.clamp(self.min_size, self.max_size);
+            if start + length > data.len() {
+                break;
+            }
+            chunks.push(Chunk::new(start, length));
+            start += length;
+        }
+        self.pending = ends[next..].iter().map(|end| end - start).collect();
+
+        while data.len() - start > self.max_size {
+            chunks.push(Chunk::new(start, self.max_size));
+            start += self.max_size;
+        }
+
+        self.sync.keep(start);
+        self.rest = data[start..].to_vec();
+        chunks
+    }
+
+    fn remainder(&self) -> &[u8] {
+        &self.rest
+    }
+
+    fn estimate_chunk_count(&self, data: &[u8]) -> usize {
+        self.inner.estimate_chunk_count(data)
+    }
+
+    fn name(&self) -> &str {
+        "BlockAlignedChunker"
+    }
+}
+
+impl Chunker for SeededChunker {
+    fn chunk_data(&mut self, data: &[u8], empty: Vec<Chunk>) -> Vec<Chunk> {
+        let mut chunks = empty;
+        let mut offset = 0;
+        while data.len() - offset >= self.next_length {
+            chunks.push(Chunk::new(offset, self.next_length));
+            offset += self.next_length;
+            self.next_length = self.generate_length();
+        }
+
+        self.rest = data[offset..].to_vec();
+        chunks
+    }
+
+    fn remainder(&self) -> &[u8] {
+        &self.rest
+    }
+
+    fn estimate_chunk_count(&self, data: &[u8]) -> usize {
+        data.len() / self.min_size + 1
+    }
+
+    fn name(&self) -> &str {
+        "SeededChunker"
+    }
 }
 
 impl Debug for RabinChunker {
@@ -161,3 +549,163 @@ impl Debug for RabinChunker {
         write!(f, "RabinCDC")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use crate::chunkers::{
+        BlockAlignedChunker, FSChunker, MaxCapChunker, ReplayChunker, SeededChunker,
+    };
+    use crate::Chunker;
+
+    #[test]
+    fn replay_chunker_cuts_at_given_offsets() {
+        let data = (0..1200).map(|i| (i % 256) as u8).collect::<Vec<_>>();
+        let mut chunker = ReplayChunker::new(vec![100, 250, 1000]);
+
+        let chunks = chunker.chunk_data(&data[..300], vec![]);
+        let ranges = chunks.iter().map(|chunk| chunk.range()).collect::<Vec<_>>();
+        assert_eq!(ranges, vec![0..100, 100..250]);
+        assert_eq!(chunker.remainder(), &data[250..300]);
+
+        let mut buffer = chunker.remainder().to_vec();
+        buffer.extend_from_slice(&data[300..]);
+        let chunks = chunker.chunk_data(&buffer, vec![]);
+        let ranges = chunks.iter().map(|chunk| chunk.range()).collect::<Vec<_>>();
+        assert_eq!(ranges, vec![0..750]);
+        assert_eq!(chunker.remainder(), &data[1000..]);
+    }
+
+    fn seeded_lengths(seed: u64) -> Vec<usize> {
+        let data = vec![0; 100000];
+        let mut chunker = SeededChunker::new(seed, 1000, 5000);
+        chunker
+            .chunk_data(&data, vec![])
+            .iter()
+            .map(|chunk| chunk.length())
+            .collect()
+    }
+
+    #[test]
+    fn seeded_chunker_is_reproducible() {
+        let lengths = seeded_lengths(42);
+        assert!(lengths
+            .iter()
+            .all(|&length| (1000..=5000).contains(&length)));
+        assert!(lengths.windows(2).any(|pair| pair[0] != pair[1]));
+
+        assert_eq!(seeded_lengths(42), lengths);
+        assert_ne!(seeded_lengths(43), lengths);
+    }
+
+    #[test]
+    fn max_cap_chunker_splits_long_chunks() {
+        let data = (0..50000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut chunker = MaxCapChunker::new(FSChunker::new(10000), 4096);
+
+        let chunks = chunker.chunk_data(&data, vec![]);
+        assert!(chunks.iter().all(|chunk| chunk.length() <= 4096));
+        assert!(chunks.iter().any(|chunk| chunk.length() == 4096));
+        assert!(chunks
+            .windows(2)
+            .all(|pair| pair[0].range().end == pair[1].offset()));
+        assert_eq!(chunks.last().unwrap().range().end, 50000);
+        assert!(chunker.remainder().is_empty());
+
+        let chunks = chunker.chunk_data(&data[..9000], vec![]);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunker.remainder(), &data[8192..9000]);
+    }
+
+    /// Feeds `data` to the chunker in parts of `part_size` bytes, giving back the remainder each time
+    /// the same way the storage does, and returns ranges of all chunks, including the last one.
+    fn stream_ranges<C: Chunker>(
+        chunker: &mut C,
+        data: &[u8],
+        part_size: usize,
+    ) -> Vec<Range<usize>> {
+        let mut ranges = vec![];
+        let mut buffer = vec![];
+        let mut position = 0;
+        for part in data.chunks(part_size) {
+            buffer.extend_from_slice(part);
+            for chunk in chunker.chunk_data(&buffer, vec![]) {
+                ranges.push(position + chunk.offset()..position + chunk.range().end);
+            }
+            let rest = chunker.remainder().to_vec();
+            position += buffer.len() - rest.len();
+            buffer = rest;
+        }
+        if !buffer.is_empty() {
+            ranges.push(position..position + buffer.len());
+        }
+        ranges
+    }
+
+    #[test]
+    fn max_cap_chunker_keeps_stateful_inner_chunker_in_sync() {
+        let data = vec![0; 30000];
+        let mut chunker =
+            MaxCapChunker::new(ReplayChunker::new(vec![3000, 10000, 11000, 25000]), 4096);
+
+        assert_eq!(
+            stream_ranges(&mut chunker, &data, 7000),
+            vec![
+                0..3000,
+                3000..7096,
+                7096..10000,
+                10000..11000,
+                11000..15096,
+                15096..19192,
+                19192..23288,
+                23288..25000,
+                25000..29096,
+                29096..30000,
+            ]
+        );
+    }
+
+    #[test]
+    fn block_aligned_chunker_aligns_cut_points() {
+        let data = (0..300000).map(|i| (i % 253) as u8).collect::<Vec<_>>();
+        let mut chunker =
+            BlockAlignedChunker::new(SeededChunker::new(7, 1000, 20000), 4096, 1000, 20000);
+
+        let mut chunks = vec![];
+        let mut buffer = vec![];
+        for part in data.chunks(100000) {
+            buffer.extend_from_slice(part);
+            for chunk in chunker.chunk_data(&buffer, vec![]) {
+                chunks.push(buffer[chunk.range()].to_vec());
+            }
+            buffer = chunker.remainder().to_vec();
+        }
+
+        assert!(chunks.len() > 10);
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.len() % 4096 == 0 && (4096..=16384).contains(&chunk.len())));
+        chunks.push(buffer);
+        assert_eq!(chunks.concat(), data);
+    }
+
+    #[test]
+    fn block_aligned_chunker_keeps_sizes_within_bounds() {
+        let data = vec![0; 300000];
+        // the maximum size of the inner chunker is not a multiple of the block size
+        let chunker =
+            || BlockAlignedChunker::new(SeededChunker::new(3, 1000, 10000), 4096, 3000, 10000);
+
+        let ranges = stream_ranges(&mut chunker(), &data, 100000);
+        assert!(ranges.iter().all(|range| range.len() <= 10000));
+        assert!(ranges[..ranges.len() - 1]
+            .iter()
+            .all(|range| range.len() == 4096 || range.len() == 8192));
+        assert!(ranges.iter().any(|range| range.len() == 8192));
+        assert_eq!(ranges.last().unwrap().end, data.len());
+
+        // the inner chunker is stateful, so it has to be kept in sync across the parts
+        assert_eq!(stream_ranges(&mut chunker(), &data, 7000), ranges);
+    }
+}
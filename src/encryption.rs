@@ -0,0 +1,71 @@
+#[cfg(feature = "encryption")]
+use std::fmt::{Debug, Formatter};
+use std::io;
+#[cfg(feature = "encryption")]
+use std::io::ErrorKind;
+
+#[cfg(feature = "encryption")]
+use aes_gcm_siv::aead::{Aead, KeyInit};
+#[cfg(feature = "encryption")]
+use aes_gcm_siv::{Aes256GcmSiv, Nonce};
+
+/// Key used to encrypt contents of a single file.
+///
+/// Chunks are encrypted deterministically, so identical chunks of one file
+/// are still deduplicated, while the same data in files with different keys is stored separately.
+#[cfg(feature = "encryption")]
+#[derive(Clone)]
+pub struct FileKey {
+    cipher: Aes256GcmSiv,
+}
+
+#[cfg(feature = "encryption")]
+impl FileKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            cipher: Aes256GcmSiv::new(&key.into()),
+        }
+    }
+
+    /// Encrypts a single chunk of data.
+    pub(crate) fn encrypt(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        // the nonce is fixed to make encryption convergent within the file
+        self.cipher
+            .encrypt(&Nonce::default(), data)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "failed to encrypt chunk"))
+    }
+
+    /// Decrypts a single chunk of data. Fails if the chunk was encrypted with another key.
+    pub(crate) fn decrypt(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        self.cipher.decrypt(&Nonce::default(), data).map_err(|_| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                "failed to decrypt chunk: the key is wrong or the data is corrupted",
+            )
+        })
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl Debug for FileKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FileKey")
+    }
+}
+
+/// Stand-in for the key when the `encryption` feature is disabled.
+/// It can't be constructed, so no file is ever encrypted.
+#[cfg(not(feature = "encryption"))]
+#[derive(Clone, Debug)]
+pub enum FileKey {}
+
+#[cfg(not(feature = "encryption"))]
+impl FileKey {
+    pub(crate) fn encrypt(&self, _data: &[u8]) -> io::Result<Vec<u8>> {
+        match *self {}
+    }
+
+    pub(crate) fn decrypt(&self, _data: &[u8]) -> io::Result<Vec<u8>> {
+        match *self {}
+    }
+}
@@ -1,33 +1,55 @@
 use std::collections::HashMap;
 use std::io;
 use std::io::ErrorKind;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
-use crate::storage::SpansInfo;
+use crate::encryption::FileKey;
+use crate::storage::{Span, SpansInfo};
 use crate::ChunkHash;
 use crate::Chunker;
 use crate::{WriteMeasurements, SEG_SIZE};
 
-/// Hashed span, starting at `offset`.
-#[derive(Debug, PartialEq, Eq, Default)]
+/// Hashed span, starting at `offset` and having the given `length`.
+/// Spans without a hash are holes, which are not stored anywhere and read as zeros.
+#[derive(Debug, PartialEq, Eq, Default, Clone)]
 pub struct FileSpan<Hash: ChunkHash> {
-    hash: Hash,
+    hash: Option<Hash>,
     offset: usize,
+    length: usize,
+}
+
+/// Part of the file's contents, as returned by [FileLayer::read_complete] and [FileLayer::read].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Extent<Hash: ChunkHash> {
+    /// Chunk stored under the given hash.
+    Chunk(Hash),
+    /// Hole of the given length, which reads as zeros.
+    Hole(usize),
 }
 
 /// A named file, doesn't store actual contents,
 /// but rather hashes for them.
+#[derive(Clone)]
 pub struct File<Hash: ChunkHash> {
     name: String,
     spans: Vec<FileSpan<Hash>>,
+    encrypted: bool,
+    chunker: String,
+    snapshot: bool,
 }
 
 /// Layer that contains all [`files`][File], accessed by their names.
 #[derive(Default)]
 pub struct FileLayer<Hash: ChunkHash> {
     files: HashMap<String, File<Hash>>,
+    open_handles: Arc<AtomicUsize>,
 }
 
 /// Handle for an open [`file`][File].
+///
+/// Each handle owns its chunker and the data left after its last write,
+/// so writes through handles to different files can be freely interleaved.
 #[derive(Debug)]
 pub struct FileHandle<C>
 where
@@ -41,13 +63,47 @@ where
     measurements: WriteMeasurements,
     // maybe not pub(crate) but something else? cannot think of anything
     pub(crate) chunker: C,
+    /// Data left after the last write, which was not yet cut into a chunk.
+    pub(crate) rest: Vec<u8>,
+    pub(crate) key: Option<FileKey>,
+    /// Error of a partially successful write, which is reported by the next write or flush.
+    pub(crate) error: Option<io::Error>,
+    open_handles: Arc<AtomicUsize>,
 }
 
 impl<Hash: ChunkHash> File<Hash> {
-    fn new(name: String) -> Self {
+    fn new(name: String, encrypted: bool, chunker: String) -> Self {
         File {
             name,
             spans: vec![],
+            encrypted,
+            chunker,
+            snapshot: false,
+        }
+    }
+
+    /// Returns the size of the file in bytes.
+    fn size(&self) -> usize {
+        self.spans
+            .last()
+            .map(|span| span.offset + span.length)
+            .unwrap_or_default()
+    }
+}
+
+impl<Hash: ChunkHash> FileSpan<Hash> {
+    fn hole(offset: usize, length: usize) -> Self {
+        Self {
+            hash: None,
+            offset,
+            length,
+        }
+    }
+
+    fn extent(&self) -> Extent<Hash> {
+        match &self.hash {
+            Some(hash) => Extent::Chunk(hash.clone()), // cloning hashes, takes a lot of time
+            None => Extent::Hole(self.length),
         }
     }
 }
@@ -56,12 +112,22 @@ impl<C> FileHandle<C>
 where
     C: Chunker,
 {
-    fn new<Hash: ChunkHash>(file: &File<Hash>, chunker: C) -> Self {
+    fn new<Hash: ChunkHash>(
+        file: &File<Hash>,
+        chunker: C,
+        key: Option<FileKey>,
+        open_handles: &Arc<AtomicUsize>,
+    ) -> Self {
+        open_handles.fetch_add(1, Ordering::Relaxed);
         FileHandle {
             file_name: file.name.clone(),
             offset: 0,
             measurements: Default::default(),
             chunker,
+            rest: vec![],
+            key,
+            error: None,
+            open_handles: open_handles.clone(),
         }
     }
 
@@ -70,36 +136,94 @@ where
         &self.file_name
     }
 
+    /// Checks if some data written through the handle is not yet stored,
+    /// i.e. the handle must be closed with [`close_file`][crate::FileSystem::close_file] to not lose it.
+    pub fn has_unflushed_data(&self) -> bool {
+        !self.rest.is_empty()
+    }
+
     /// Closes handle and returns [`WriteMeasurements`] made while file was open.
     pub(crate) fn close(self) -> WriteMeasurements {
         self.measurements
     }
 }
 
+impl<C> Drop for FileHandle<C>
+where
+    C: Chunker,
+{
+    fn drop(&mut self) {
+        self.open_handles.fetch_sub(1, Ordering::Relaxed);
+        if !std::thread::panicking() {
+            debug_assert!(
+                !self.has_unflushed_data(),
+                "handle to the file {} was dropped without closing, written data is lost",
+                self.file_name
+            );
+        }
+    }
+}
+
 impl<Hash: ChunkHash> FileLayer<Hash> {
-    /// Creates a [`file`][File] and returns its [`FileHandle`]
+    /// Creates a [`file`][File] and returns its [`FileHandle`].
+    /// If the `key` is given, contents of the file are encrypted with it.
     pub fn create<C: Chunker>(
         &mut self,
         name: String,
         chunker: C,
         create_new: bool,
+        key: Option<FileKey>,
     ) -> io::Result<FileHandle<C>> {
         if !create_new && self.files.contains_key(&name) {
             return Err(ErrorKind::AlreadyExists.into());
         }
 
-        let file = File::new(name.clone());
+        let file = File::new(name.clone(), key.is_some(), chunker.name().to_string());
         let _ = self.files.insert(name.clone(), file);
         let written_file = self.files.get(&name).unwrap();
-        Ok(FileHandle::new(written_file, chunker))
+        Ok(FileHandle::new(
+            written_file,
+            chunker,
+            key,
+            &self.open_handles,
+        ))
     }
 
-    /// Opens a [`file`][File] based on its name and returns its [`FileHandle`]
-    pub fn open<C: Chunker>(&self, name: &str, chunker: C) -> io::Result<FileHandle<C>> {
-        self.files
-            .get(name)
-            .map(|file| FileHandle::new(file, chunker))
-            .ok_or(ErrorKind::NotFound.into())
+    /// Opens a [`file`][File] based on its name and returns its [`FileHandle`].
+    ///
+    /// Encrypted files can only be opened with a `key`,
+    /// otherwise `ErrorKind::PermissionDenied` is returned.
+    pub fn open<C: Chunker>(
+        &self,
+        name: &str,
+        chunker: C,
+        key: Option<FileKey>,
+    ) -> io::Result<FileHandle<C>> {
+        let file = self.files.get(name).ok_or(ErrorKind::NotFound)?;
+        match (file.encrypted, key.is_some()) {
+            (true, false) => Err(ErrorKind::PermissionDenied.into()),
+            (false, true) => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "the file is not encrypted",
+            )),
+            _ => Ok(FileHandle::new(file, chunker, key, &self.open_handles)),
+        }
+    }
+
+    /// Opens a [`file`][File] with the given name if it exists, or creates it otherwise, and returns its [`FileHandle`].
+    ///
+    /// An existing file is not truncated, so data written through the handle is appended to it.
+    /// Encrypted files cannot be opened this way, `ErrorKind::PermissionDenied` is returned for them.
+    pub fn create_or_open<C: Chunker>(
+        &mut self,
+        name: String,
+        chunker: C,
+    ) -> io::Result<FileHandle<C>> {
+        if self.files.contains_key(&name) {
+            self.open(&name, chunker, None)
+        } else {
+            self.create(name, chunker, false, None)
+        }
     }
 
     /// Returns reference to a file using [`FileHandle`] that corresponds to it.
@@ -112,49 +236,50 @@ impl<Hash: ChunkHash> FileLayer<Hash> {
         self.files.get_mut(&handle.file_name).unwrap()
     }
 
-    /// Reads all hashes of the file, from beginning to end.
-    pub fn read_complete<C: Chunker>(&self, handle: &FileHandle<C>) -> Vec<Hash> {
+    /// Reads all extents of the file, from beginning to end.
+    pub fn read_complete<C: Chunker>(&self, handle: &FileHandle<C>) -> Vec<Extent<Hash>> {
         let file = self.find_file(handle);
-        file.spans
-            .iter()
-            .map(|span| span.hash.clone()) // cloning hashes, takes a lot of time
-            .collect()
+        file.spans.iter().map(FileSpan::extent).collect()
+    }
+
+    /// Returns an iterator over extents of the file with the given name, from beginning to end.
+    pub fn extents(&self, name: &str) -> io::Result<impl Iterator<Item = Extent<Hash>> + '_> {
+        Ok(self.get_file(name)?.spans.iter().map(FileSpan::extent))
     }
 
     /// Writes spans to the end of the file.
     pub fn write<C: Chunker>(&mut self, handle: &mut FileHandle<C>, info: SpansInfo<Hash>) {
         let file = self.find_file_mut(handle);
+        let mut offset = file.size();
         for span in info.spans {
             file.spans.push(FileSpan {
-                hash: span.hash,
-                offset: handle.offset,
+                hash: Some(span.hash),
+                offset,
+                length: span.length,
             });
+            offset += span.length;
             handle.offset += span.length;
         }
 
         handle.measurements += info.measurements;
     }
 
-    /// Reads 1 MB of data from the open file and returns received hashes,
+    /// Reads 1 MB of data from the open file and returns received extents,
     /// starting point is based on the `FileHandle`'s offset.
-    pub fn read<C: Chunker>(&self, handle: &mut FileHandle<C>) -> Vec<Hash> {
+    pub fn read<C: Chunker>(&self, handle: &mut FileHandle<C>) -> Vec<Extent<Hash>> {
         let file = self.find_file(handle);
 
-        let mut bytes_read = 0;
-        let mut last_offset = handle.offset;
+        let mut end = handle.offset;
         let hashes = file
             .spans
             .iter()
             .skip_while(|span| span.offset < handle.offset) // find current span in the file
-            .take_while(|span| {
-                bytes_read += span.offset - last_offset;
-                last_offset = span.offset;
-                bytes_read < SEG_SIZE
-            }) // take 1 MB of spans after current one
-            .map(|span| span.hash.clone()) // take their hashes
+            .take_while(|span| span.offset < handle.offset + SEG_SIZE) // take 1 MB of spans after current one
+            .inspect(|span| end = span.offset + span.length)
+            .map(FileSpan::extent) // take their hashes
             .collect();
 
-        handle.offset += bytes_read;
+        handle.offset = end;
 
         hashes
     }
@@ -163,6 +288,372 @@ impl<Hash: ChunkHash> FileLayer<Hash> {
     pub fn file_exists(&self, name: &str) -> bool {
         self.files.contains_key(name)
     }
+
+    /// Returns a file with the given name, or `ErrorKind::NotFound` if it doesn't exist.
+    fn get_file(&self, name: &str) -> io::Result<&File<Hash>> {
+        self.files.get(name).ok_or(ErrorKind::NotFound.into())
+    }
+
+    /// Returns a mutable reference to a file with the given name,
+    /// or `ErrorKind::NotFound` if it doesn't exist.
+    fn get_file_mut(&mut self, name: &str) -> io::Result<&mut File<Hash>> {
+        self.files.get_mut(name).ok_or(ErrorKind::NotFound.into())
+    }
+
+    /// Returns the number of handles to files of this layer that are currently open.
+    pub fn open_handles(&self) -> usize {
+        self.open_handles.load(Ordering::Relaxed)
+    }
+
+    /// Returns names of all files that start with the given prefix.
+    pub fn list_files_with_prefix(&self, prefix: &str) -> Vec<String> {
+        self.files
+            .keys()
+            .filter(|name| name.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns names of all files along with their sizes in bytes.
+    pub fn list_files_with_sizes(&self) -> Vec<(String, u64)> {
+        self.files
+            .values()
+            .map(|file| (file.name.clone(), file.size() as u64))
+            .collect()
+    }
+
+    /// Returns hashes and lengths of all spans of the file with the given name, from beginning to end.
+    /// Holes are skipped.
+    pub fn manifest(&self, name: &str) -> io::Result<Vec<(Hash, usize)>> {
+        Ok(self
+            .get_file(name)?
+            .spans
+            .iter()
+            .filter_map(|span| span.hash.clone().map(|hash| (hash, span.length)))
+            .collect())
+    }
+
+    /// Returns lengths of all distinct chunks of the file with the given name, by their hashes.
+    pub fn chunk_lengths(&self, name: &str) -> io::Result<HashMap<&Hash, usize>> {
+        Ok(self
+            .get_file(name)?
+            .spans
+            .iter()
+            .filter_map(|span| span.hash.as_ref().map(|hash| (hash, span.length)))
+            .collect())
+    }
+
+    /// Counts how many times each chunk is referenced by spans of all files.
+    pub fn reference_counts(&self) -> HashMap<&Hash, usize> {
+        let mut counts = HashMap::new();
+        for span in self.files.values().flat_map(|file| &file.spans) {
+            if let Some(hash) = &span.hash {
+                *counts.entry(hash).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    /// Returns each distinct chunk referenced by the files along with its length and reference count,
+    /// sorted from the most referenced chunk to the least referenced one.
+    pub fn reuse_map(&self) -> Vec<(Hash, usize, usize)> {
+        let mut chunks: HashMap<&Hash, (usize, usize)> = HashMap::new();
+        for span in self.files.values().flat_map(|file| &file.spans) {
+            if let Some(hash) = &span.hash {
+                chunks.entry(hash).or_insert((span.length, 0)).1 += 1;
+            }
+        }
+
+        let mut map = chunks
+            .into_iter()
+            .map(|(hash, (length, count))| (hash.clone(), length, count))
+            .collect::<Vec<_>>();
+        map.sort_by_key(|(_, _, count)| std::cmp::Reverse(*count));
+        map
+    }
+
+    /// Returns the size of the file with the given name in bytes.
+    pub fn file_size(&self, name: &str) -> io::Result<usize> {
+        Ok(self.get_file(name)?.size())
+    }
+
+    /// Creates a file named `dst` with the same contents as the file named `src`, sharing its chunks.
+    ///
+    /// Returns `ErrorKind::NotFound` if `src` doesn't exist, or `ErrorKind::AlreadyExists` if `dst` does.
+    pub fn copy(&mut self, src: &str, dst: &str) -> io::Result<()> {
+        self.duplicate(src, dst, false)
+    }
+
+    /// Creates a snapshot named `dst` of the file named `src`, in the same way as [`copy`][FileLayer::copy],
+    /// but marks it as a snapshot, so that it is listed by [`list_snapshots`][FileLayer::list_snapshots].
+    pub fn snapshot(&mut self, src: &str, dst: &str) -> io::Result<()> {
+        self.duplicate(src, dst, true)
+    }
+
+    /// Returns names of all files that were created as snapshots.
+    pub fn list_snapshots(&self) -> Vec<String> {
+        self.files
+            .values()
+            .filter(|file| file.snapshot)
+            .map(|file| file.name.clone())
+            .collect()
+    }
+
+    /// Creates a file named `dst` that shares spans with the file named `src`.
+    fn duplicate(&mut self, src: &str, dst: &str, snapshot: bool) -> io::Result<()> {
+        if self.files.contains_key(dst) {
+            return Err(ErrorKind::AlreadyExists.into());
+        }
+
+        let file = self.get_file(src)?;
+        let copy = File {
+            name: dst.to_string(),
+            spans: file.spans.clone(),
+            encrypted: file.encrypted,
+            chunker: file.chunker.clone(),
+            snapshot,
+        };
+        self.files.insert(dst.to_string(), copy);
+        Ok(())
+    }
+
+    /// Adds all files of the `other` layer to this one.
+    ///
+    /// If a file with the same name already exists, the added file is renamed to `name (n)`
+    /// with the smallest `n` that is not taken. Returns names that the added files got.
+    pub fn absorb(&mut self, other: &FileLayer<Hash>) -> Vec<String> {
+        let mut names = Vec::with_capacity(other.files.len());
+        for file in other.files.values() {
+            let mut name = file.name.clone();
+            let mut n = 1;
+            while self.files.contains_key(&name) {
+                name = format!("{} ({n})", file.name);
+                n += 1;
+            }
+
+            let mut file = file.clone();
+            file.name = name.clone();
+            self.files.insert(name.clone(), file);
+            names.push(name);
+        }
+        names
+    }
+
+    /// Returns a name that starts with `prefix` and is not taken by any file.
+    pub fn unused_name(&self, prefix: &str) -> String {
+        let mut name = prefix.to_string();
+        let mut n = 1;
+        while self.files.contains_key(&name) {
+            name = format!("{prefix} ({n})");
+            n += 1;
+        }
+        name
+    }
+
+    /// Renames the file named `src` to `dst`, replacing the file named `dst` if it exists.
+    /// The replaced file stays a snapshot if it was one.
+    pub fn replace(&mut self, src: &str, dst: &str) -> io::Result<()> {
+        let mut file = self.files.remove(src).ok_or(ErrorKind::NotFound)?;
+        file.name = dst.to_string();
+        if let Some(replaced) = self.files.get(dst) {
+            file.snapshot = replaced.snapshot;
+        }
+        self.files.insert(dst.to_string(), file);
+        Ok(())
+    }
+
+    /// Removes the file with the given name. Its chunks are kept in the storage.
+    pub fn remove(&mut self, name: &str) -> io::Result<()> {
+        self.files.remove(name).ok_or(ErrorKind::NotFound)?;
+        Ok(())
+    }
+
+    /// Returns the [`name`][Chunker::name] of the chunker that the file with the given name was created with.
+    pub fn chunker_of(&self, name: &str) -> Option<&str> {
+        self.files.get(name).map(|file| file.chunker.as_str())
+    }
+
+    /// Checks if the file with the given name is encrypted.
+    pub fn is_encrypted(&self, name: &str) -> io::Result<bool> {
+        Ok(self.get_file(name)?.encrypted)
+    }
+
+    /// Returns extents of the file with the given name that overlap the byte range `start..end`,
+    /// along with the offset in the file at which the first of them starts.
+    pub fn read_range(
+        &self,
+        name: &str,
+        start: usize,
+        end: usize,
+    ) -> io::Result<(Vec<Extent<Hash>>, usize)> {
+        let spans = self
+            .get_file(name)?
+            .spans
+            .iter()
+            .skip_while(|span| span.offset + span.length <= start)
+            .take_while(|span| span.offset < end)
+            .collect::<Vec<_>>();
+        let first_offset = spans.first().map(|span| span.offset).unwrap_or(start);
+        Ok((
+            spans.into_iter().map(FileSpan::extent).collect(),
+            first_offset,
+        ))
+    }
+
+    /// Returns hashes of all spans of the file with the given name, from beginning to end.
+    /// Holes are skipped.
+    pub fn hashes(&self, name: &str) -> io::Result<Vec<Hash>> {
+        Ok(self
+            .get_file(name)?
+            .spans
+            .iter()
+            .filter_map(|span| span.hash.clone())
+            .collect())
+    }
+
+    /// Returns the hash of the chunk that is cut in the middle when the file with the given name
+    /// is truncated to `new_len` bytes, along with the length of its part that must be kept.
+    pub fn cut_chunk(&self, name: &str, new_len: usize) -> io::Result<Option<(Hash, usize)>> {
+        Ok(self
+            .get_file(name)?
+            .spans
+            .iter()
+            .find(|span| span.offset < new_len && new_len < span.offset + span.length)
+            .and_then(|span| {
+                let hash = span.hash.clone()?;
+                Some((hash, new_len - span.offset))
+            }))
+    }
+
+    /// Cuts the file with the given name, so that it is not longer than `new_len` bytes.
+    ///
+    /// If the cut falls in the middle of a chunk, the chunk is removed too,
+    /// and its kept part (see [`cut_chunk`][Self::cut_chunk]) has to be appended separately.
+    /// Holes are shortened in place instead.
+    pub fn truncate(&mut self, name: &str, new_len: usize) -> io::Result<()> {
+        let file = self.get_file_mut(name)?;
+
+        let mut kept = file
+            .spans
+            .iter()
+            .take_while(|span| span.offset + span.length <= new_len)
+            .count();
+        if let Some(span) = file
+            .spans
+            .get_mut(kept)
+            .filter(|span| span.offset < new_len && span.hash.is_none())
+        {
+            span.length = new_len - span.offset;
+            kept += 1;
+        }
+
+        file.spans.truncate(kept);
+        Ok(())
+    }
+
+    /// Appends spans to the end of the file with the given name.
+    pub fn append(&mut self, name: &str, spans: Vec<Span<Hash>>) -> io::Result<()> {
+        let file = self.get_file_mut(name)?;
+        let mut offset = file.size();
+        for span in spans {
+            file.spans.push(FileSpan {
+                hash: Some(span.hash),
+                offset,
+                length: span.length,
+            });
+            offset += span.length;
+        }
+        Ok(())
+    }
+
+    /// Checks that `length` bytes can be placed into the file at the given `offset` with [`insert`][Self::insert],
+    /// once the data left in the handle is appended to the end of the file.
+    ///
+    /// Returns `ErrorKind::InvalidInput` if the bytes would overwrite stored or unflushed data.
+    pub fn check_insert<C: Chunker>(
+        &self,
+        handle: &FileHandle<C>,
+        offset: usize,
+        length: usize,
+    ) -> io::Result<()> {
+        let file = self.get_file(&handle.file_name)?;
+        let size = file.size();
+        let fits = if offset < size {
+            file.spans
+                .iter()
+                .find(|span| span.offset + span.length > offset)
+                .is_some_and(|hole| {
+                    hole.hash.is_none() && offset + length <= hole.offset + hole.length
+                })
+        } else {
+            offset >= size + handle.rest.len()
+        };
+
+        if !fits {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "data can only be written into a hole or past the end of the file",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Places spans into the file at the given `offset`.
+    ///
+    /// If the offset is beyond the end of the file, the gap is left as a hole.
+    /// Otherwise, the spans must fit into an existing hole, which is split around them;
+    /// overwriting stored data returns `ErrorKind::InvalidInput`.
+    pub fn insert<C: Chunker>(
+        &mut self,
+        handle: &mut FileHandle<C>,
+        offset: usize,
+        info: SpansInfo<Hash>,
+    ) -> io::Result<()> {
+        let length = info.spans.iter().map(|span| span.length).sum::<usize>();
+        self.check_insert(handle, offset, length)?;
+
+        let file = self.get_file_mut(&handle.file_name)?;
+        let size = file.size();
+
+        let mut index = file.spans.len();
+        let mut end = offset + length;
+        let mut replacement = Vec::with_capacity(info.spans.len() + 2);
+        if offset > size {
+            replacement.push(FileSpan::hole(size, offset - size));
+        } else if offset < size {
+            index = file
+                .spans
+                .iter()
+                .position(|span| span.offset + span.length > offset)
+                .unwrap();
+            let hole = &file.spans[index];
+            if offset > hole.offset {
+                replacement.push(FileSpan::hole(hole.offset, offset - hole.offset));
+            }
+            end = hole.offset + hole.length;
+            file.spans.remove(index);
+        }
+
+        let mut current = offset;
+        for span in info.spans {
+            replacement.push(FileSpan {
+                hash: Some(span.hash),
+                offset: current,
+                length: span.length,
+            });
+            current += span.length;
+        }
+        if end > current {
+            replacement.push(FileSpan::hole(current, end - current));
+        }
+        file.spans.splice(index..index, replacement);
+
+        if offset + length > size {
+            handle.offset = offset + length;
+        }
+        handle.measurements += info.measurements;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -176,7 +667,8 @@ mod tests {
     fn file_layer_create_file() {
         let mut fl: FileLayer<Vec<u8>> = FileLayer::default();
         let name = "hello".to_string();
-        fl.create(name.clone(), FSChunker::new(4096), true).unwrap();
+        fl.create(name.clone(), FSChunker::new(4096), true, None)
+            .unwrap();
 
         assert_eq!(fl.files.get(&name).unwrap().name, "hello");
         assert_eq!(fl.files.get(&name).unwrap().spans, vec![]);
@@ -185,10 +677,10 @@ mod tests {
     #[test]
     fn cant_create_two_files_with_same_name() {
         let mut fl: FileLayer<Vec<u8>> = FileLayer::default();
-        fl.create("hello".to_string(), FSChunker::new(4096), false)
+        fl.create("hello".to_string(), FSChunker::new(4096), false, None)
             .unwrap();
 
-        let result = fl.create("hello".to_string(), FSChunker::new(4096), false);
+        let result = fl.create("hello".to_string(), FSChunker::new(4096), false, None);
         assert!(result.is_err());
         assert_eq!(result.unwrap_err().kind(), ErrorKind::AlreadyExists);
     }
@@ -1,7 +1,10 @@
+use std::marker::PhantomData;
+
 use sha2::digest::Output;
 use sha2::{Digest, Sha256};
+use xxhash_rust::xxh3::xxh3_64;
 
-use crate::Hasher;
+use crate::{ChunkHash, Hasher, IncrementalHasher};
 
 #[derive(Debug)]
 pub struct SimpleHasher;
@@ -27,3 +30,232 @@ impl Hasher for Sha256Hasher {
         Digest::finalize_reset(&mut self.hasher)
     }
 }
+
+impl IncrementalHasher for Sha256Hasher {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(&mut self.hasher, data);
+    }
+
+    fn finalize(&mut self) -> Self::Hash {
+        Digest::finalize_reset(&mut self.hasher)
+    }
+}
+
+/// [Sha256Hasher] that prepends a salt to the data before hashing it.
+///
+/// Running the same data with different salts shows how the results depend on the particular hash values.
+/// With an empty salt, it produces the same hashes as [Sha256Hasher].
+#[derive(Debug, Default)]
+pub struct SaltedSha256Hasher {
+    hasher: Sha256,
+    salt: Vec<u8>,
+}
+
+impl SaltedSha256Hasher {
+    pub fn new(salt: Vec<u8>) -> Self {
+        Self {
+            hasher: Sha256::default(),
+            salt,
+        }
+    }
+}
+
+impl Hasher for SaltedSha256Hasher {
+    type Hash = Output<Sha256>;
+
+    fn hash(&mut self, data: &[u8]) -> Self::Hash {
+        Digest::update(&mut self.hasher, &self.salt);
+        Digest::update(&mut self.hasher, data);
+        Digest::finalize_reset(&mut self.hasher)
+    }
+}
+
+/// [Sha256Hasher] whose hashes are truncated to the first `bytes` bytes.
+///
+/// Shorter hashes take less space, but make collisions more likely.
+#[derive(Debug)]
+pub struct TruncatedShaHasher {
+    hasher: Sha256,
+    bytes: usize,
+}
+
+impl TruncatedShaHasher {
+    /// Creates a hasher producing hashes of the given length, which must not exceed 32 bytes.
+    pub fn new(bytes: usize) -> Self {
+        assert!(bytes <= 32, "SHA-256 hashes are only 32 bytes long");
+        Self {
+            hasher: Sha256::default(),
+            bytes,
+        }
+    }
+}
+
+impl Hasher for TruncatedShaHasher {
+    type Hash = Vec<u8>;
+
+    fn hash(&mut self, data: &[u8]) -> Self::Hash {
+        Digest::update(&mut self.hasher, data);
+        Digest::finalize_reset(&mut self.hasher)[..self.bytes].to_vec()
+    }
+}
+
+/// Non-cryptographic hasher based on XXH3, producing 64-bit hashes.
+///
+/// Much faster than [Sha256Hasher], but collisions are far more likely,
+/// so it is only suitable for measurements where that risk is acceptable.
+#[derive(Debug, Default)]
+pub struct XxHasher;
+
+impl Hasher for XxHasher {
+    type Hash = u64;
+
+    fn hash(&mut self, data: &[u8]) -> Self::Hash {
+        xxh3_64(data)
+    }
+}
+
+/// Hasher that calls the given function to hash the data.
+///
+/// Allows using any hash function without defining a new type implementing [Hasher].
+pub struct FnHasher<H, F>
+where
+    H: ChunkHash,
+    F: FnMut(&[u8]) -> H,
+{
+    function: F,
+    _hash: PhantomData<H>,
+}
+
+impl<H, F> FnHasher<H, F>
+where
+    H: ChunkHash,
+    F: FnMut(&[u8]) -> H,
+{
+    pub fn new(function: F) -> Self {
+        Self {
+            function,
+            _hash: PhantomData,
+        }
+    }
+}
+
+impl<H, F> Hasher for FnHasher<H, F>
+where
+    H: ChunkHash,
+    F: FnMut(&[u8]) -> H,
+{
+    type Hash = H;
+
+    fn hash(&mut self, data: &[u8]) -> Self::Hash {
+        (self.function)(data)
+    }
+}
+
+/// Adaptor that gives any [Hasher] an [IncrementalHasher] interface
+/// by collecting the data into a buffer and hashing it at once on [`finalize`][IncrementalHasher::finalize].
+#[derive(Debug, Default)]
+pub struct BufferedHasher<H: Hasher> {
+    hasher: H,
+    buffer: Vec<u8>,
+}
+
+impl<H: Hasher> BufferedHasher<H> {
+    pub fn new(hasher: H) -> Self {
+        Self {
+            hasher,
+            buffer: vec![],
+        }
+    }
+}
+
+impl<H: Hasher> Hasher for BufferedHasher<H> {
+    type Hash = H::Hash;
+
+    fn hash(&mut self, data: &[u8]) -> Self::Hash {
+        self.hasher.hash(data)
+    }
+}
+
+impl<H: Hasher> IncrementalHasher for BufferedHasher<H> {
+    fn update(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    fn finalize(&mut self) -> Self::Hash {
+        let hash = self.hasher.hash(&self.buffer);
+        self.buffer.clear();
+        hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::base::HashMapBase;
+    use crate::chunkers::FSChunker;
+    use crate::hashers::{
+        BufferedHasher, SaltedSha256Hasher, Sha256Hasher, SimpleHasher, TruncatedShaHasher,
+        XxHasher,
+    };
+    use crate::{FileSystem, Hasher, IncrementalHasher};
+
+    fn assert_incremental_matches_hash<H: IncrementalHasher>(mut hasher: H) {
+        let data = (0..10000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let expected = hasher.hash(&data);
+
+        for parts in [1, 3, 7] {
+            for part in data.chunks(data.len() / parts) {
+                hasher.update(part);
+            }
+            assert!(hasher.finalize() == expected);
+        }
+        assert!(hasher.finalize() == hasher.hash(&[]));
+    }
+
+    #[test]
+    fn sha256_update_and_finalize_equal_hash() {
+        assert_incremental_matches_hash(Sha256Hasher::default());
+    }
+
+    #[test]
+    fn buffered_update_and_finalize_equal_hash() {
+        assert_incremental_matches_hash(BufferedHasher::new(SimpleHasher));
+        assert_incremental_matches_hash(BufferedHasher::new(XxHasher));
+    }
+
+    #[test]
+    fn salted_hashes_depend_on_salt() {
+        let chunk = [1; 4096];
+        let first = SaltedSha256Hasher::new(vec![1, 2, 3]).hash(&chunk);
+        let second = SaltedSha256Hasher::new(vec![4, 5, 6]).hash(&chunk);
+        assert_ne!(first, second);
+
+        let unsalted = SaltedSha256Hasher::default().hash(&chunk);
+        assert_eq!(unsalted, Sha256Hasher::default().hash(&chunk));
+    }
+
+    #[test]
+    fn truncated_hashes_are_prefixes_of_full_hash() {
+        let chunk = [1; 4096];
+        let full = Sha256Hasher::default().hash(&chunk);
+
+        let short = TruncatedShaHasher::new(8).hash(&chunk);
+        assert_eq!(short, full[..8]);
+        assert_eq!(TruncatedShaHasher::new(32).hash(&chunk), full[..]);
+    }
+
+    #[test]
+    fn truncated_hashes_deduplicate_same_as_full_ones() {
+        // 200 blocks with 25 distinct contents, so no collisions are expected
+        let data = (0..200)
+            .flat_map(|i| (0..4096).map(move |j| (j * (i % 25 + 1) % 251) as u8))
+            .collect::<Vec<_>>();
+
+        let estimate = |bytes| {
+            let mut fs = FileSystem::new(HashMapBase::default(), TruncatedShaHasher::new(bytes));
+            fs.estimate_dedup(FSChunker::new(4096), data.as_slice())
+                .unwrap()
+        };
+        assert_eq!(estimate(32), 8.0);
+        assert_eq!(estimate(8), estimate(32));
+    }
+}
@@ -1,7 +1,11 @@
+use std::io::ErrorKind;
 use std::ops::{Add, AddAssign};
 use std::time::Duration;
 use std::{hash, io};
 
+#[cfg(feature = "encryption")]
+pub use encryption::FileKey;
+pub use shared::SharedFileSystem;
 pub use system::{FileOpener, FileSystem, OpenError};
 
 #[cfg(feature = "chunkers")]
@@ -10,7 +14,10 @@ pub mod chunkers;
 pub mod hashers;
 
 pub mod base;
+pub mod bench;
+mod encryption;
 mod file_layer;
+mod shared;
 mod storage;
 mod system;
 
@@ -74,6 +81,13 @@ pub trait Chunker {
     /// data buffer. Used to pre-allocate the buffer with the required size so that allocation times are not counted
     /// towards total chunking time.
     fn estimate_chunk_count(&self, data: &[u8]) -> usize;
+
+    /// Returns a short label of the chunking algorithm, recorded for each file created with it.
+    ///
+    /// Should stay the same between versions, so that the label can be compared with stored ones.
+    fn name(&self) -> &str {
+        "unknown"
+    }
 }
 
 /// Functionality for an object that hashes the input.
@@ -84,6 +98,18 @@ pub trait Hasher {
     fn hash(&mut self, data: &[u8]) -> Self::Hash;
 }
 
+/// Hasher that can be fed the data incrementally, without collecting it into one buffer first.
+///
+/// Data given to [`update`][IncrementalHasher::update] calls followed by [`finalize`][IncrementalHasher::finalize]
+/// must hash to the same value as the concatenation of that data given to [`hash`][Hasher::hash].
+pub trait IncrementalHasher: Hasher {
+    /// Feeds a part of the data to the hasher.
+    fn update(&mut self, data: &[u8]);
+
+    /// Returns the hash of all data given since the last call to `finalize` and resets the hasher.
+    fn finalize(&mut self) -> Self::Hash;
+}
+
 /// Serves as base functionality for storing the actual data.
 pub trait Database<Hash: ChunkHash> {
     /// Saves given data to the underlying storage.
@@ -92,9 +118,69 @@ pub trait Database<Hash: ChunkHash> {
     /// Clones and returns the data corresponding to the given hashes, or returns Error(NotFound),
     /// if some of the hashes were not found.
     fn retrieve(&self, request: Vec<Hash>) -> io::Result<Vec<Vec<u8>>>;
+
+    /// Clones and returns the data corresponding to the given hashes, with `None` in place of data that is not found.
+    ///
+    /// Unlike [`retrieve`][Database::retrieve], it doesn't fail if some of the hashes are missing,
+    /// so it can be used to find out which ones are. Other errors, e.g. corrupted data, are still returned.
+    /// By default, retrieves the hashes one by one.
+    fn retrieve_partial(&self, request: &[Hash]) -> io::Result<Vec<Option<Vec<u8>>>> {
+        request
+            .iter()
+            .map(|hash| match self.retrieve(vec![hash.clone()]) {
+                Ok(data) => Ok(data.into_iter().next()),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            })
+            .collect()
+    }
+
+    /// Checks if the data with the given hash is stored in the database.
+    ///
+    /// By default, tries to [`retrieve`][Database::retrieve] the data, which may be expensive.
+    fn contains(&self, hash: &Hash) -> bool {
+        self.retrieve(vec![hash.clone()]).is_ok()
+    }
+
+    /// Returns `true` if [`contains`][Database::contains] is cheap compared to [`retrieve`][Database::retrieve],
+    /// e.g. when it only checks an in-memory index. Helps callers decide which one to use when batching requests.
+    fn contains_is_cheap(&self) -> bool {
+        false
+    }
+
+    /// Returns the number of segments stored in the database, or `None` if it can't be found out cheaply.
+    fn len(&self) -> Option<usize> {
+        None
+    }
+
+    /// Checks if the database has no segments stored, or returns `None` if it can't be found out cheaply.
+    fn is_empty(&self) -> Option<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Returns the amount of bytes the stored data actually takes, or `None` if it is unknown.
+    ///
+    /// May differ from the total length of the saved segments, e.g. when the data is compressed.
+    fn stored_size(&self) -> Option<usize> {
+        None
+    }
+
+    /// Makes sure that all saved data is persisted, e.g. by flushing it to disk.
+    ///
+    /// Does nothing by default, which suits databases that only keep the data in memory.
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Database whose stored data can be iterated over.
+pub trait IterableDatabase<Hash: ChunkHash>: Database<Hash> {
+    /// Returns an iterator over all stored hashes along with their data.
+    fn iterator(&self) -> Box<dyn Iterator<Item = (&Hash, &[u8])> + '_>;
 }
 
 /// A data segment with corresponding hash.
+#[derive(Clone)]
 pub struct Segment<Hash: ChunkHash> {
     pub hash: Hash,
     pub data: Vec<u8>,
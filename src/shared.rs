@@ -0,0 +1,92 @@
+use std::io;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::file_layer::FileHandle;
+use crate::{ChunkHash, Chunker, Database, FileSystem, Hasher, WriteMeasurements};
+
+/// Thread-safe wrapper around the [FileSystem], allowing it to be shared between threads.
+///
+/// Reads are done under a shared lock, so multiple readers can work at the same time,
+/// while writes, creating and closing files take an exclusive lock.
+///
+/// To be shared between threads (for example, through [`Arc`][std::sync::Arc] or [`std::thread::scope`]),
+/// the database `B`, the hasher `H` and the `Hash` must all be [`Send`] and [`Sync`].
+pub struct SharedFileSystem<B, H, Hash>
+where
+    B: Database<Hash>,
+    H: Hasher<Hash = Hash>,
+    Hash: ChunkHash,
+{
+    fs: RwLock<FileSystem<B, H, Hash>>,
+}
+
+impl<B, H, Hash> SharedFileSystem<B, H, Hash>
+where
+    B: Database<Hash>,
+    H: Hasher<Hash = Hash>,
+    Hash: ChunkHash,
+{
+    /// Wraps the given [FileSystem].
+    pub fn new(fs: FileSystem<B, H, Hash>) -> Self {
+        Self {
+            fs: RwLock::new(fs),
+        }
+    }
+
+    /// Unwraps the underlying [FileSystem].
+    pub fn into_inner(self) -> FileSystem<B, H, Hash> {
+        self.fs.into_inner().expect("file system lock is poisoned")
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, FileSystem<B, H, Hash>> {
+        self.fs.read().expect("file system lock is poisoned")
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, FileSystem<B, H, Hash>> {
+        self.fs.write().expect("file system lock is poisoned")
+    }
+
+    /// Checks if the file with the given `name` exists.
+    pub fn file_exists(&self, name: &str) -> bool {
+        self.read().file_exists(name)
+    }
+
+    /// Opens a file with the given name. See [FileSystem::open_file].
+    pub fn open_file<C: Chunker>(&self, name: &str, chunker: C) -> io::Result<FileHandle<C>> {
+        self.read().open_file(name, chunker)
+    }
+
+    /// Creates a file with the given name. See [FileSystem::create_file].
+    pub fn create_file<C: Chunker>(
+        &self,
+        name: String,
+        chunker: C,
+        create_new: bool,
+    ) -> io::Result<FileHandle<C>> {
+        self.write().create_file(name, chunker, create_new)
+    }
+
+    /// Writes given data to the file. See [FileSystem::write_to_file].
+    pub fn write_to_file<C: Chunker>(
+        &self,
+        handle: &mut FileHandle<C>,
+        data: &[u8],
+    ) -> io::Result<usize> {
+        self.write().write_to_file(handle, data)
+    }
+
+    /// Closes the file. See [FileSystem::close_file].
+    pub fn close_file<C: Chunker>(&self, handle: FileHandle<C>) -> io::Result<WriteMeasurements> {
+        self.write().close_file(handle)
+    }
+
+    /// Reads all contents of the file. See [FileSystem::read_file_complete].
+    pub fn read_file_complete<C: Chunker>(&self, handle: &FileHandle<C>) -> io::Result<Vec<u8>> {
+        self.read().read_file_complete(handle)
+    }
+
+    /// Reads 1 MB of data from the file. See [FileSystem::read_from_file].
+    pub fn read_from_file<C: Chunker>(&self, handle: &mut FileHandle<C>) -> io::Result<Vec<u8>> {
+        self.read().read_from_file(handle)
+    }
+}
@@ -1,12 +1,15 @@
 use std::io;
+use std::io::ErrorKind;
 use std::time::{Duration, Instant};
 
-use crate::ChunkHash;
+use crate::encryption::FileKey;
 pub use crate::Chunker;
 pub use crate::Database;
 pub use crate::Hasher;
+use crate::IterableDatabase;
 use crate::Segment;
 use crate::WriteMeasurements;
+use crate::{Chunk, ChunkHash};
 
 /// Hashed span in a [`file`][crate::file_layer::File] with a certain length.
 #[derive(Debug)]
@@ -51,6 +54,8 @@ where
     }
 
     /// Writes 1 MB of data to the [`base`][crate::base::Base] storage after deduplication.
+    /// The data is prepended with `rest`, the leftover of the previous write, which is replaced by the new leftover.
+    /// If the `key` is given, chunks are encrypted with it before being hashed and stored.
     ///
     /// Returns resulting lengths of [chunks][crate::chunker::Chunk] with corresponding hash,
     /// along with amount of time spent on chunking and hashing.
@@ -58,21 +63,135 @@ where
         &mut self,
         data: &[u8],
         chunker: &mut C,
+        rest: &mut Vec<u8>,
+        key: Option<&FileKey>,
     ) -> io::Result<SpansInfo<Hash>> {
-        let mut writer = StorageWriter::new(chunker, &mut self.hasher);
-        writer.write(data, &mut self.base)
+        let mut writer = StorageWriter::new(chunker, &mut self.hasher, rest, key);
+        writer.write(data, &mut self.base, false)
+    }
+
+    /// Writes the last part of the data to the [`base`][crate::base::Base] storage after deduplication.
+    ///
+    /// Unlike [`write`][Storage::write], the leftover of the chunker is stored as the last chunk right away,
+    /// so no [`flush`][Storage::flush] is needed afterwards.
+    pub fn write_final<C: Chunker>(
+        &mut self,
+        data: &[u8],
+        chunker: &mut C,
+        rest: &mut Vec<u8>,
+        key: Option<&FileKey>,
+    ) -> io::Result<SpansInfo<Hash>> {
+        let mut writer = StorageWriter::new(chunker, &mut self.hasher, rest, key);
+        writer.write(data, &mut self.base, true)
     }
 
     /// Flushes remaining data to the storage and returns its [`span`][Span] with hashing and chunking times.
-    pub fn flush<C: Chunker>(&mut self, chunker: &mut C) -> io::Result<SpansInfo<Hash>> {
-        let mut writer = StorageWriter::new(chunker, &mut self.hasher);
+    /// The `rest` is emptied afterwards.
+    pub fn flush<C: Chunker>(
+        &mut self,
+        chunker: &mut C,
+        rest: &mut Vec<u8>,
+        key: Option<&FileKey>,
+    ) -> io::Result<SpansInfo<Hash>> {
+        let mut writer = StorageWriter::new(chunker, &mut self.hasher, rest, key);
         writer.flush(&mut self.base)
     }
 
+    /// Chunks and hashes the data the same way as [`write`][Storage::write] or [`write_final`][Storage::write_final],
+    /// but gives the resulting segments to the given `base` instead of the underlying one.
+    ///
+    /// Used to examine how the data would be stored without storing it.
+    pub fn write_into<C: Chunker, D: Database<Hash>>(
+        &mut self,
+        data: &[u8],
+        chunker: &mut C,
+        rest: &mut Vec<u8>,
+        is_final: bool,
+        base: &mut D,
+    ) -> io::Result<SpansInfo<Hash>> {
+        let mut writer = StorageWriter::new(chunker, &mut self.hasher, rest, None);
+        writer.write(data, base, is_final)
+    }
+
+    /// Saves already hashed segments to the [`base`][crate::base::Base] as they are.
+    pub fn save(&mut self, segments: Vec<Segment<Hash>>) -> io::Result<()> {
+        self.base.save(segments)
+    }
+
+    /// Persists the data saved to the [`base`][crate::base::Base].
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.base.sync()
+    }
+
+    /// Returns a reference to the underlying [`base`][crate::base::Base].
+    pub fn database(&self) -> &B {
+        &self.base
+    }
+
+    /// Stores the given data as a single chunk, without splitting it, and returns its [`span`][Span].
+    pub fn write_unchunked(&mut self, data: &[u8]) -> io::Result<Span<Hash>> {
+        let hash = self.hasher.hash(data);
+        self.base
+            .save(vec![Segment::new(hash.clone(), data.to_vec())])?;
+        Ok(Span::new(hash, data.len()))
+    }
+
     /// Retrieves the data from the storage based on hashes of the data [`segments`][Segment],
     /// or Error(NotFound) if some of the hashes were not present in the base.
-    pub fn retrieve(&self, request: Vec<Hash>) -> io::Result<Vec<Vec<u8>>> {
-        self.base.retrieve(request)
+    /// If the `key` is given, retrieved chunks are decrypted with it.
+    pub fn retrieve(&self, request: Vec<Hash>, key: Option<&FileKey>) -> io::Result<Vec<Vec<u8>>> {
+        let data = self.base.retrieve(request)?;
+        match key {
+            None => Ok(data),
+            Some(key) => data.iter().map(|chunk| key.decrypt(chunk)).collect(),
+        }
+    }
+
+    /// Retrieves the data like [`retrieve`][Storage::retrieve], but re-hashes every chunk first
+    /// and returns `ErrorKind::InvalidData` if it doesn't match the requested hash.
+    pub fn retrieve_verified(
+        &mut self,
+        request: Vec<Hash>,
+        key: Option<&FileKey>,
+    ) -> io::Result<Vec<Vec<u8>>> {
+        let data = self.base.retrieve(request.clone())?;
+        if request
+            .iter()
+            .zip(data.iter())
+            .any(|(hash, chunk)| self.hasher.hash(chunk) != *hash)
+        {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "retrieved chunk does not match its hash",
+            ));
+        }
+
+        match key {
+            None => Ok(data),
+            Some(key) => data.iter().map(|chunk| key.decrypt(chunk)).collect(),
+        }
+    }
+}
+
+impl<B, H, Hash> Storage<B, H, Hash>
+where
+    B: IterableDatabase<Hash>,
+    H: Hasher<Hash = Hash>,
+    Hash: ChunkHash,
+{
+    /// Returns an iterator over all hashes and data stored in the [`base`][crate::base::Base].
+    pub fn iterator(&self) -> Box<dyn Iterator<Item = (&Hash, &[u8])> + '_> {
+        self.base.iterator()
+    }
+
+    /// Re-hashes all data stored in the [`base`][crate::base::Base]
+    /// and returns hashes that do not match the data stored under them.
+    pub fn verify_integrity(&mut self) -> Vec<Hash> {
+        self.base
+            .iterator()
+            .filter(|(hash, data)| self.hasher.hash(data) != **hash)
+            .map(|(hash, _)| hash.clone())
+            .collect()
     }
 }
 
@@ -87,6 +206,8 @@ where
 {
     chunker: &'handle mut C,
     hasher: &'handle mut H,
+    rest: &'handle mut Vec<u8>,
+    key: Option<&'handle FileKey>,
 }
 
 impl<'handle, C, H> StorageWriter<'handle, C, H>
@@ -94,11 +215,30 @@ where
     C: Chunker,
     H: Hasher,
 {
-    fn new(chunker: &'handle mut C, hasher: &'handle mut H) -> Self {
-        Self { chunker, hasher }
+    fn new(
+        chunker: &'handle mut C,
+        hasher: &'handle mut H,
+        rest: &'handle mut Vec<u8>,
+        key: Option<&'handle FileKey>,
+    ) -> Self {
+        Self {
+            chunker,
+            hasher,
+            rest,
+            key,
+        }
+    }
+
+    /// Prepares chunk data for storing, encrypting it if the writer has a key.
+    fn seal(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self.key {
+            None => Ok(data.to_vec()),
+            Some(key) => key.encrypt(data),
+        }
     }
 
     /// Writes 1 MB of data to the [`base`][crate::base::Base] storage after deduplication.
+    /// If `is_final` is set, the leftover of the chunker is stored as the last chunk.
     ///
     /// Returns resulting lengths of [chunks][crate::chunker::Chunk] with corresponding hash,
     /// along with amount of time spent on chunking and hashing.
@@ -106,40 +246,56 @@ where
         &mut self,
         data: &[u8],
         base: &mut B,
+        is_final: bool,
     ) -> io::Result<SpansInfo<H::Hash>> {
         //debug_assert!(data.len() == SEG_SIZE); // we assume that all given data segments are 1MB long for now
 
-        let mut buffer = self.chunker.remainder().to_vec();
+        let mut buffer = self.rest.clone();
         buffer.extend_from_slice(data);
 
         let empty = Vec::with_capacity(self.chunker.estimate_chunk_count(&buffer));
 
         let start = Instant::now();
-        let chunks = self.chunker.chunk_data(&buffer, empty);
+        let mut chunks = self.chunker.chunk_data(&buffer, empty);
         let chunk_time = start.elapsed();
 
+        let rest_length = self.chunker.remainder().len();
+        if is_final && rest_length > 0 {
+            chunks.push(Chunk::new(buffer.len() - rest_length, rest_length));
+        }
+
+        let sealed = chunks
+            .iter()
+            .map(|chunk| self.seal(&buffer[chunk.range()])) // cloning buffer data again
+            .collect::<io::Result<Vec<_>>>()?;
+
         let start = Instant::now();
-        let hashes = chunks
+        let hashes = sealed
             .iter()
-            .map(|chunk| self.hasher.hash(&buffer[chunk.range()]))
+            .map(|data| self.hasher.hash(data))
             .collect::<Vec<_>>();
         let hash_time = start.elapsed();
 
         let segments = hashes
             .into_iter()
-            .zip(
-                chunks.iter().map(|chunk| buffer[chunk.range()].to_vec()), // cloning buffer data again
-            )
+            .zip(sealed)
             .map(|(hash, data)| Segment::new(hash, data))
             .collect::<Vec<_>>();
 
         // have to copy hashes? or do something else?
         let spans = segments
             .iter()
-            .map(|segment| Span::new(segment.hash.clone(), segment.data.len()))
+            .zip(chunks.iter())
+            .map(|(segment, chunk)| Span::new(segment.hash.clone(), chunk.length()))
             .collect();
         base.save(segments)?;
 
+        self.rest.clear();
+        if !is_final {
+            self.rest
+                .extend_from_slice(&buffer[buffer.len() - rest_length..]);
+        }
+
         Ok(SpansInfo {
             spans,
             measurements: WriteMeasurements::new(chunk_time, hash_time),
@@ -149,22 +305,24 @@ where
     /// Flushes remaining data to the storage and returns its [`span`][Span] with hashing and chunking times.
     fn flush<B: Database<H::Hash>>(&mut self, base: &mut B) -> io::Result<SpansInfo<H::Hash>> {
         // is this necessary?
-        if self.chunker.remainder().is_empty() {
+        if self.rest.is_empty() {
             return Ok(SpansInfo {
                 spans: vec![],
                 measurements: Default::default(),
             });
         }
 
-        let remainder = self.chunker.remainder().to_vec();
+        let remainder = self.seal(self.rest)?;
         let start = Instant::now();
         let hash = self.hasher.hash(&remainder);
         let hash_time = start.elapsed();
 
-        let segment = Segment::new(hash.clone(), remainder.clone());
+        let length = self.rest.len();
+        let segment = Segment::new(hash.clone(), remainder);
         base.save(vec![segment])?;
+        self.rest.clear();
 
-        let span = Span::new(hash, remainder.len());
+        let span = Span::new(hash, length);
         Ok(SpansInfo {
             spans: vec![span],
             measurements: WriteMeasurements::new(Duration::default(), hash_time),
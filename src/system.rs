@@ -1,14 +1,22 @@
 use std::cmp::min;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::io;
-use std::io::ErrorKind;
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
 
-use crate::file_layer::{FileHandle, FileLayer};
-use crate::storage::Storage;
+#[cfg(feature = "hashers")]
+use sha2::{Digest, Sha256};
+
+#[cfg(feature = "mmap")]
+use crate::base::MmapBase;
+use crate::encryption::FileKey;
+use crate::file_layer::{Extent, FileHandle, FileLayer};
+use crate::storage::{SpansInfo, Storage};
 use crate::WriteMeasurements;
 use crate::{ChunkHash, SEG_SIZE};
-use crate::{Chunker, Database, Hasher};
+use crate::{Chunker, Database, Hasher, IterableDatabase, Segment};
 
 /// A file system provided by chunkfs.
 pub struct FileSystem<B, H, Hash>
@@ -19,6 +27,7 @@ where
 {
     storage: Storage<B, H, Hash>,
     file_layer: FileLayer<Hash>,
+    total_measurements: WriteMeasurements,
 }
 
 impl<B, H, Hash> FileSystem<B, H, Hash>
@@ -32,6 +41,7 @@ where
         Self {
             storage: Storage::new(base, hasher),
             file_layer: Default::default(),
+            total_measurements: Default::default(),
         }
     }
 
@@ -40,10 +50,235 @@ where
         self.file_layer.file_exists(name)
     }
 
+    /// Returns the ratio of the total size of all files to the amount of bytes their data takes in the database,
+    /// combining the effect of deduplication and of compression done by the database, if any.
+    ///
+    /// Returns `None` if the database doesn't report its [`stored_size`][Database::stored_size]
+    /// or nothing is stored yet.
+    pub fn compression_ratio(&self) -> Option<f64> {
+        let stored = self.storage.database().stored_size()?;
+        if stored == 0 {
+            return None;
+        }
+
+        let logical = self
+            .list_files_with_sizes()
+            .iter()
+            .map(|(_, size)| size)
+            .sum::<u64>();
+        Some(logical as f64 / stored as f64)
+    }
+
+    /// Persists all chunks stored in the database, see [Database::sync].
+    ///
+    /// Leftovers kept in open handles are not stored yet, so they must be flushed or closed beforehand.
+    pub fn sync_all(&mut self) -> io::Result<()> {
+        self.storage.sync()
+    }
+
+    /// Returns [WriteMeasurements] accumulated over all handles that were closed in this file system.
+    pub fn total_write_measurements(&self) -> WriteMeasurements {
+        self.total_measurements
+    }
+
+    /// Returns the number of file handles that are currently open, i.e. not closed or dropped yet.
+    pub fn open_handles(&self) -> usize {
+        self.file_layer.open_handles()
+    }
+
+    /// Returns names of all files in the file system that start with the given prefix, in no particular order.
+    pub fn list_files_with_prefix(&self, prefix: &str) -> Vec<String> {
+        self.file_layer.list_files_with_prefix(prefix)
+    }
+
+    /// Returns names of all files in the file system along with their sizes in bytes, in no particular order.
+    pub fn list_files_with_sizes(&self) -> Vec<(String, u64)> {
+        self.file_layer.list_files_with_sizes()
+    }
+
+    /// Returns the distribution of reference counts of chunks over all files.
+    ///
+    /// Each key is a number of times a chunk is referenced by the files,
+    /// and each value is the number of chunks referenced that many times.
+    pub fn reference_count_distribution(&self) -> HashMap<usize, usize> {
+        let mut distribution = HashMap::new();
+        for count in self.file_layer.reference_counts().into_values() {
+            *distribution.entry(count).or_insert(0) += 1;
+        }
+        distribution
+    }
+
+    /// Returns `(hash, length, reference count)` of every distinct chunk referenced by the files,
+    /// from the most referenced one to the least referenced one.
+    ///
+    /// Shows which chunks contribute the most to the deduplication.
+    pub fn export_reuse_map(&self) -> Vec<(Hash, usize, usize)> {
+        self.file_layer.reuse_map()
+    }
+
+    /// Returns hashes and lengths of all chunks of the file with the given name, in the order they appear in the file.
+    ///
+    /// Can be used to compare files without reading them. Holes left by [`write_sparse`][Self::write_sparse] are skipped.
+    pub fn file_manifest(&self, name: &str) -> io::Result<Vec<(Hash, usize)>> {
+        self.file_layer.manifest(name)
+    }
+
+    /// Returns a SHA-256 digest of the sequence of chunk hashes and holes that make up the file with the given name.
+    ///
+    /// Files that consist of the same chunks get the same digest, which is cheaper than comparing their contents.
+    /// Files with the same contents split into different chunks, e.g. by different chunkers, get different digests.
+    /// Every chunk is encoded as its tag, hash length and hash bytes, and every hole as its tag and length,
+    /// so the digest doesn't depend on the platform or the compiler version.
+    #[cfg(feature = "hashers")]
+    pub fn file_digest(&self, name: &str) -> io::Result<[u8; 32]>
+    where
+        Hash: AsRef<[u8]>,
+    {
+        let mut digest = Sha256::new();
+        for extent in self.file_layer.extents(name)? {
+            match extent {
+                Extent::Chunk(hash) => {
+                    let hash = hash.as_ref();
+                    digest.update([0]);
+                    digest.update((hash.len() as u64).to_le_bytes());
+                    digest.update(hash);
+                }
+                Extent::Hole(length) => {
+                    digest.update([1]);
+                    digest.update((length as u64).to_le_bytes());
+                }
+            }
+        }
+        Ok(digest.finalize().into())
+    }
+
+    /// Returns the total size of distinct chunks that are referenced by both files with the given names.
+    pub fn shared_chunk_bytes(&self, a: &str, b: &str) -> io::Result<usize> {
+        let a = self.file_layer.chunk_lengths(a)?;
+        let b = self.file_layer.chunk_lengths(b)?;
+        Ok(a.iter()
+            .filter(|(hash, _)| b.contains_key(*hash))
+            .map(|(_, length)| length)
+            .sum())
+    }
+
+    /// Adds all chunks and files of the `other` file system to this one.
+    ///
+    /// Chunks that are already stored are not duplicated, so both file systems
+    /// must use the same kind of hashes. Files whose names are already taken are renamed to `name (n)`.
+    /// Returns the names that the added files got.
+    pub fn absorb<B2, H2>(&mut self, other: &FileSystem<B2, H2, Hash>) -> io::Result<Vec<String>>
+    where
+        B2: IterableDatabase<Hash>,
+        H2: Hasher<Hash = Hash>,
+    {
+        let segments = other
+            .storage_iterator()
+            .filter(|(hash, _)| !self.storage.database().contains(hash))
+            .map(|(hash, data)| Segment::new(hash.clone(), data.to_vec()))
+            .collect::<Vec<_>>();
+        self.storage.save(segments)?;
+
+        Ok(self.file_layer.absorb(&other.file_layer))
+    }
+
+    /// Copies the file named `src` to a new file named `dst` without reading its data:
+    /// the copy refers to the same chunks, so nothing new is stored in the database.
+    ///
+    /// Returns `ErrorKind::NotFound` if `src` doesn't exist, or `ErrorKind::AlreadyExists` if `dst` does.
+    /// A copy of an encrypted file is encrypted with the same key.
+    pub fn copy_file(&mut self, src: &str, dst: &str) -> io::Result<()> {
+        self.file_layer.copy(src, dst)
+    }
+
+    /// Splits the contents of the file with the given name into chunks again, using the given `chunker`.
+    ///
+    /// The contents stay the same, but the file now refers to the new chunks and is marked as created with the new chunker.
+    /// If the new chunks can't be stored, the file is left unchanged.
+    /// Chunks that are no longer referenced are kept in the database. Holes are stored as zeros.
+    /// Encrypted files cannot be re-chunked, `ErrorKind::PermissionDenied` is returned for them.
+    pub fn rechunk_file<C: Chunker>(
+        &mut self,
+        name: &str,
+        chunker: C,
+    ) -> io::Result<WriteMeasurements> {
+        if self.file_layer.is_encrypted(name)? {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+
+        let data = self.read_range(name, 0, self.file_layer.file_size(name)?)?;
+
+        // the data is written to a temporary file first, so that the original one is kept if writing fails
+        let temp_name = self.file_layer.unused_name(&format!("{name}.rechunk"));
+        let handle = self
+            .file_layer
+            .create(temp_name.clone(), chunker, false, None)?;
+        match self.write_final(handle, &data) {
+            Ok(measurements) => {
+                self.file_layer.replace(&temp_name, name)?;
+                Ok(measurements)
+            }
+            Err(e) => {
+                self.file_layer.remove(&temp_name)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Takes a snapshot of the file named `src`, saving it as a file named `dst`.
+    ///
+    /// Like [`copy_file`][Self::copy_file], it stores nothing new and the snapshot doesn't change when `src` is written to
+    /// or overwritten later, but the snapshot is also listed by [`list_snapshots`][Self::list_snapshots].
+    pub fn snapshot(&mut self, src: &str, dst: &str) -> io::Result<()> {
+        self.file_layer.snapshot(src, dst)
+    }
+
+    /// Returns names of all files that were created with [`snapshot`][Self::snapshot].
+    pub fn list_snapshots(&self) -> Vec<String> {
+        self.file_layer.list_snapshots()
+    }
+
+    /// Returns the [`name`][Chunker::name] of the chunker that the file with the given name was created with,
+    /// or `None` if the file doesn't exist.
+    pub fn chunker_of(&self, name: &str) -> Option<String> {
+        self.file_layer.chunker_of(name).map(str::to_string)
+    }
+
+    /// Checks if all chunks of the file with the given name are present in the database.
+    ///
+    /// Unlike [`file_exists`][Self::file_exists], which only checks the name,
+    /// it detects files whose data was partially lost, e.g. after the database was corrupted.
+    pub fn file_is_complete(&self, name: &str) -> io::Result<bool> {
+        let base = self.storage.database();
+        Ok(self
+            .file_layer
+            .hashes(name)?
+            .iter()
+            .all(|hash| base.contains(hash)))
+    }
+
     /// Tries to open a file with the given name and returns its `FileHandle` if it exists,
     /// or `None`, if it doesn't.
+    ///
+    /// Reading doesn't depend on the `chunker`, but data written through the handle is appended
+    /// using it, so if it differs from the one the file was created with (see [`chunker_of`][Self::chunker_of]),
+    /// chunk boundaries after the seam won't match the ones the original chunker would produce.
     pub fn open_file<C: Chunker>(&self, name: &str, chunker: C) -> io::Result<FileHandle<C>> {
-        self.file_layer.open(name, chunker)
+        self.file_layer.open(name, chunker, None)
+    }
+
+    /// Opens an encrypted file with the given name and returns its `FileHandle`.
+    ///
+    /// The `key` must be the same one that the file was created with, otherwise reading from it
+    /// returns `ErrorKind::InvalidData`.
+    #[cfg(feature = "encryption")]
+    pub fn open_file_encrypted<C: Chunker>(
+        &self,
+        name: &str,
+        chunker: C,
+        key: FileKey,
+    ) -> io::Result<FileHandle<C>> {
+        self.file_layer.open(name, chunker, Some(key))
     }
 
     /// Creates a file with the given name and returns its `FileHandle`.
@@ -54,61 +289,693 @@ where
         chunker: C,
         create_new: bool,
     ) -> io::Result<FileHandle<C>> {
-        self.file_layer.create(name, chunker, create_new)
+        self.file_layer.create(name, chunker, create_new, None)
+    }
+
+    /// Opens a file with the given name if it exists, or creates it otherwise, and returns its `FileHandle`.
+    ///
+    /// Unlike [`create_file`][Self::create_file], an existing file keeps its contents and written data is appended to it.
+    /// Returns `ErrorKind::PermissionDenied` if the existing file is encrypted.
+    pub fn create_or_open<C: Chunker>(
+        &mut self,
+        name: String,
+        chunker: C,
+    ) -> io::Result<FileHandle<C>> {
+        self.file_layer.create_or_open(name, chunker)
+    }
+
+    /// Creates a file with the given name, whose contents are encrypted with the given `key`,
+    /// and returns its `FileHandle`. The file can only be read using the same key.
+    ///
+    /// Duplicate chunks are deduplicated within the file, but not across files with different keys.
+    #[cfg(feature = "encryption")]
+    pub fn create_file_encrypted<C: Chunker>(
+        &mut self,
+        name: String,
+        chunker: C,
+        key: FileKey,
+        create_new: bool,
+    ) -> io::Result<FileHandle<C>> {
+        self.file_layer.create(name, chunker, create_new, Some(key))
     }
 
-    /// Writes given data to the file.
+    /// Writes given data to the file and returns the number of bytes that were committed to spans of the file
+    /// during this call.
+    ///
+    /// The count is not measured against `data`. The tail of the data that is not yet cut into a chunk is kept
+    /// in the handle until a later write or [`flush_file`][Self::flush_file], so the count doesn't include it,
+    /// but it does include such a tail left by an earlier write. It may be both smaller and larger than `data.len()`,
+    /// so it can't be used to find out which part of `data` to write again.
+    ///
+    /// Data is given to the storage segment by segment. If the underlying storage fails
+    /// after some segments were already accepted, the amount of bytes committed up to that point
+    /// is returned, and the error is returned by the next write or flush of the handle.
     pub fn write_to_file<C: Chunker>(
         &mut self,
         handle: &mut FileHandle<C>,
         data: &[u8],
-    ) -> io::Result<()> {
+    ) -> io::Result<usize> {
+        self.write_segments(handle, data)
+            .or_else(|partial| partial.into_result(handle))
+    }
+
+    /// Gives the data to the storage segment by segment and returns the number of bytes committed to spans of the file.
+    ///
+    /// Fails right away with the error deferred by a previous partial write, if there is one.
+    fn write_segments<C: Chunker>(
+        &mut self,
+        handle: &mut FileHandle<C>,
+        data: &[u8],
+    ) -> Result<usize, PartialWrite> {
+        if let Some(error) = handle.error.take() {
+            return Err(PartialWrite::new(0, false, error));
+        }
+
+        let mut committed = 0;
         let mut current = 0;
-        let mut all_spans = vec![];
         while current < data.len() {
             let remaining = data.len() - current;
             let to_process = min(SEG_SIZE, remaining);
 
             let spans = self
                 .storage
-                .write(&data[current..current + to_process], &mut handle.chunker)?;
-            all_spans.push(spans);
+                .write(
+                    &data[current..current + to_process],
+                    &mut handle.chunker,
+                    &mut handle.rest,
+                    handle.key.as_ref(),
+                )
+                .map_err(|error| PartialWrite::new(committed, current > 0, error))?;
+            committed += spans.spans.iter().map(|span| span.length).sum::<usize>();
+            self.file_layer.write(handle, spans);
 
             current += to_process;
         }
 
-        for spans in all_spans {
-            self.file_layer.write(handle, spans);
+        Ok(committed)
+    }
+
+    /// Reads all data from the `reader` and writes it to the file. Returns the number of bytes that were committed,
+    /// counted the same way as by [`write_to_file`][Self::write_to_file].
+    ///
+    /// Spans are committed to the file after every segment, so memory usage doesn't grow with the length of the stream.
+    pub fn write_from_stream<C: Chunker, R: Read>(
+        &mut self,
+        handle: &mut FileHandle<C>,
+        reader: R,
+    ) -> io::Result<usize> {
+        self.write_from_stream_buffered(handle, reader, SEG_SIZE)
+    }
+
+    /// Reads all data from the `reader`, up to `read_buf_size` bytes at a time, and writes it to the file.
+    /// Returns the number of bytes that were committed, see [`write_from_stream`][Self::write_from_stream].
+    ///
+    /// Data is still given to the chunker in segments of [SEG_SIZE] bytes,
+    /// so the resulting spans don't depend on `read_buf_size`.
+    pub fn write_from_stream_buffered<C: Chunker, R: Read>(
+        &mut self,
+        handle: &mut FileHandle<C>,
+        mut reader: R,
+        read_buf_size: usize,
+    ) -> io::Result<usize> {
+        let mut read_buf = vec![0; read_buf_size.max(1)];
+        let mut pending = Vec::with_capacity(SEG_SIZE + read_buf.len());
+        let mut written = 0;
+        let mut accepted = false;
+        loop {
+            let read = match reader.read(&mut read_buf) {
+                Ok(read) => read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if read == 0 {
+                break;
+            }
+            pending.extend_from_slice(&read_buf[..read]);
+
+            let full = pending.len() / SEG_SIZE * SEG_SIZE;
+            if full > 0 {
+                // nothing else is written after a failure, so that no gap is left in the file
+                match self.write_segments(handle, &pending[..full]) {
+                    Ok(committed) => written += committed,
+                    Err(partial) => {
+                        return PartialWrite::new(
+                            written + partial.committed,
+                            accepted || partial.accepted,
+                            partial.error,
+                        )
+                        .into_result(handle)
+                    }
+                }
+                accepted = true;
+                pending.drain(..full);
+            }
         }
 
-        Ok(())
+        match self.write_segments(handle, &pending) {
+            Ok(committed) => Ok(written + committed),
+            Err(partial) => PartialWrite::new(
+                written + partial.committed,
+                accepted || partial.accepted,
+                partial.error,
+            )
+            .into_result(handle),
+        }
+    }
+
+    /// Writes given data to the file as its last part and closes the file.
+    ///
+    /// Produces the same spans as [`write_to_file`][Self::write_to_file] followed by [`close_file`][Self::close_file],
+    /// but the leftover of the chunker is stored together with the last segment instead of being deferred to a flush.
+    /// Returns [WriteMeasurements] containing chunking and hashing times.
+    pub fn write_final<C: Chunker>(
+        &mut self,
+        mut handle: FileHandle<C>,
+        data: &[u8],
+    ) -> io::Result<WriteMeasurements> {
+        if data.is_empty() {
+            return self.close_file(handle);
+        }
+
+        let spans = match self.write_last_part(&mut handle, data) {
+            Ok(spans) => spans,
+            Err(e) => {
+                // the handle is consumed, so the data kept in it can't be stored anymore
+                handle.rest.clear();
+                return Err(e);
+            }
+        };
+        self.file_layer.write(&mut handle, spans);
+
+        let measurements = handle.close();
+        self.total_measurements += measurements;
+        Ok(measurements)
+    }
+
+    /// Writes all segments of the data except for the last one, and then the last one with [Storage::write_final].
+    fn write_last_part<C: Chunker>(
+        &mut self,
+        handle: &mut FileHandle<C>,
+        data: &[u8],
+    ) -> io::Result<SpansInfo<Hash>> {
+        let last_start = (data.len() - 1) / SEG_SIZE * SEG_SIZE;
+        if let Err(partial) = self.write_segments(handle, &data[..last_start]) {
+            return Err(partial.error);
+        }
+
+        self.storage.write_final(
+            &data[last_start..],
+            &mut handle.chunker,
+            &mut handle.rest,
+            handle.key.as_ref(),
+        )
     }
 
     /// Closes the file and ensures that all data that was written to it
     /// is stored. Returns [WriteMeasurements] containing chunking and hashing times.
+    ///
+    /// If the data can't be stored, the error is returned and the data kept in the handle is lost.
     pub fn close_file<C: Chunker>(
         &mut self,
         mut handle: FileHandle<C>,
     ) -> io::Result<WriteMeasurements> {
-        let span = self.storage.flush(&mut handle.chunker)?;
-        self.file_layer.write(&mut handle, span);
+        if let Err(e) = self.flush_file(&mut handle) {
+            handle.rest.clear();
+            return Err(e);
+        }
+
+        let measurements = handle.close();
+        self.total_measurements += measurements;
+        Ok(measurements)
+    }
+
+    /// Ensures that all data that was written to the file is stored, keeping the file open.
+    ///
+    /// Data that was not yet cut into a chunk is stored as a separate chunk,
+    /// and subsequent writes start a new chunk after it.
+    /// Returns [WriteMeasurements] of the flush itself.
+    pub fn flush_file<C: Chunker>(
+        &mut self,
+        handle: &mut FileHandle<C>,
+    ) -> io::Result<WriteMeasurements> {
+        if let Some(error) = handle.error.take() {
+            return Err(error);
+        }
+
+        let span =
+            self.storage
+                .flush(&mut handle.chunker, &mut handle.rest, handle.key.as_ref())?;
+        let measurements = span.measurements;
+        self.file_layer.write(handle, span);
+
+        Ok(measurements)
+    }
+
+    /// Writes given data to the file at the given `offset`, independently of the rest of the file.
+    ///
+    /// If the offset is beyond the end of the file, the gap between them is left as a hole,
+    /// which takes no space in the storage and reads as zeros. Data may also be written into an existing hole,
+    /// but not over data that is already stored, in which case `ErrorKind::InvalidInput` is returned.
+    /// Data written to the file before is flushed first, so it can't be overwritten either.
+    /// The range is checked before anything is flushed or stored. Returns the number of bytes that were written.
+    ///
+    /// The data is split into chunks with the chunker of the handle, so its state moves on as with any other write.
+    pub fn write_sparse<C: Chunker>(
+        &mut self,
+        handle: &mut FileHandle<C>,
+        offset: usize,
+        data: &[u8],
+    ) -> io::Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        self.file_layer.check_insert(handle, offset, data.len())?;
+        self.flush_file(handle)?;
 
-        Ok(handle.close())
+        let mut spans = vec![];
+        let mut measurements = WriteMeasurements::default();
+        let mut rest = vec![];
+        let mut current = 0;
+        while current < data.len() {
+            let to_process = min(SEG_SIZE, data.len() - current);
+            let is_final = current + to_process == data.len();
+            let segment = &data[current..current + to_process];
+
+            let info = if is_final {
+                self.storage.write_final(
+                    segment,
+                    &mut handle.chunker,
+                    &mut rest,
+                    handle.key.as_ref(),
+                )?
+            } else {
+                self.storage
+                    .write(segment, &mut handle.chunker, &mut rest, handle.key.as_ref())?
+            };
+            spans.extend(info.spans);
+            measurements += info.measurements;
+
+            current += to_process;
+        }
+
+        self.file_layer.insert(
+            handle,
+            offset,
+            SpansInfo {
+                spans,
+                measurements,
+            },
+        )?;
+        Ok(data.len())
+    }
+
+    /// Truncates or extends the file with the given name, so that its size becomes exactly `new_len` bytes.
+    ///
+    /// If the file is cut in the middle of a chunk, the kept part of the chunk is stored as a new chunk.
+    /// If the file is extended, new space is filled with zeros.
+    /// Encrypted files cannot be truncated, `ErrorKind::PermissionDenied` is returned for them.
+    pub fn truncate_file(&mut self, name: &str, new_len: u64) -> io::Result<()> {
+        if self.file_layer.is_encrypted(name)? {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+
+        let new_len = usize::try_from(new_len).map_err(|_| {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                "the new length doesn't fit into memory",
+            )
+        })?;
+        let size = self.file_layer.file_size(name)?;
+        if new_len <= size {
+            // the kept part of the cut chunk is stored first, so that the file stays unchanged if storing fails
+            let mut spans = vec![];
+            if let Some((hash, length)) = self.file_layer.cut_chunk(name, new_len)? {
+                let data = self.storage.retrieve(vec![hash], None)?.concat();
+                spans.push(self.storage.write_unchunked(&data[..length])?);
+            }
+            self.file_layer.truncate(name, new_len)?;
+            return self.file_layer.append(name, spans);
+        }
+
+        let mut spans = vec![];
+        let mut remaining = new_len - size;
+        while remaining > 0 {
+            let length = min(SEG_SIZE, remaining);
+            spans.push(self.storage.write_unchunked(&vec![0; length])?);
+            remaining -= length;
+        }
+        self.file_layer.append(name, spans)
     }
 
     /// Reads all contents of the file from beginning to end and returns them.
     pub fn read_file_complete<C: Chunker>(&self, handle: &FileHandle<C>) -> io::Result<Vec<u8>> {
-        let hashes = self.file_layer.read_complete(handle);
-        Ok(self.storage.retrieve(hashes)?.concat()) // it assumes that all retrieved data segments are in correct order
+        let extents = self.file_layer.read_complete(handle);
+        self.retrieve_extents(extents, handle.key.as_ref())
     }
 
     /// Reads 1 MB of data from a file and returns it.
-    pub fn read_from_file<C: Chunker>(
-        &mut self,
+    pub fn read_from_file<C: Chunker>(&self, handle: &mut FileHandle<C>) -> io::Result<Vec<u8>> {
+        let extents = self.file_layer.read(handle);
+        self.retrieve_extents(extents, handle.key.as_ref())
+    }
+
+    /// Reads 1 MB of data from a file into the given buffer and returns the number of bytes read.
+    ///
+    /// The buffer is cleared first, so the same buffer can be reused across calls without reallocating.
+    pub fn read_into<C: Chunker>(
+        &self,
         handle: &mut FileHandle<C>,
+        buf: &mut Vec<u8>,
+    ) -> io::Result<usize> {
+        buf.clear();
+        let extents = self.file_layer.read(handle);
+        self.retrieve_extents_into(extents, handle.key.as_ref(), buf)?;
+        Ok(buf.len())
+    }
+
+    /// Returns an iterator over contents of the file with the given name, one chunk at a time.
+    ///
+    /// Each chunk is retrieved from the database only when the iterator reaches it,
+    /// so only one chunk is kept in memory. Holes are yielded as zero-filled chunks.
+    /// Encrypted files cannot be read this way, `ErrorKind::PermissionDenied` is returned for them.
+    pub fn chunk_iter(
+        &self,
+        name: &str,
+    ) -> io::Result<impl Iterator<Item = io::Result<Vec<u8>>> + '_> {
+        if self.file_layer.is_encrypted(name)? {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+
+        Ok(self.file_layer.extents(name)?.map(|extent| match extent {
+            Extent::Chunk(hash) => self
+                .storage
+                .retrieve(vec![hash], None)
+                .map(|mut chunks| chunks.remove(0)),
+            Extent::Hole(length) => Ok(vec![0; length]),
+        }))
+    }
+
+    /// Reads `len` bytes of the given files, starting at `offset`, as if they were concatenated into one.
+    ///
+    /// The range may cross file boundaries; if it goes beyond the end of the last file, fewer bytes are returned.
+    /// Encrypted files cannot be read this way, `ErrorKind::PermissionDenied` is returned for them.
+    pub fn read_concat(&self, names: &[&str], offset: usize, len: usize) -> io::Result<Vec<u8>> {
+        let mut sizes = Vec::with_capacity(names.len());
+        for name in names {
+            if self.file_layer.is_encrypted(name)? {
+                return Err(ErrorKind::PermissionDenied.into());
+            }
+            sizes.push(self.file_layer.file_size(name)?);
+        }
+
+        let total = sizes.iter().sum::<usize>();
+        if offset >= total {
+            return Ok(vec![]);
+        }
+        let end = offset.saturating_add(len).min(total);
+
+        let mut data = Vec::with_capacity(end - offset);
+        let mut file_start = 0;
+        for (name, size) in names.iter().zip(sizes) {
+            let start = offset.max(file_start);
+            let file_end = end.min(file_start + size);
+            if start < file_end {
+                data.extend(self.read_range(name, start - file_start, file_end - file_start)?);
+            }
+            file_start += size;
+        }
+        Ok(data)
+    }
+
+    /// Estimates the deduplication ratio of the data read from `reader` without storing it.
+    ///
+    /// The data is chunked and hashed the same way as when it is written to a file,
+    /// but only hashes of the chunks are kept. Returns the ratio of the total size of the data
+    /// to the size of its unique chunks, i.e. `1.0` if nothing is deduplicated.
+    /// Chunks already stored in the file system are not taken into account.
+    pub fn estimate_dedup<C: Chunker, R: Read>(
+        &mut self,
+        mut chunker: C,
+        mut reader: R,
+    ) -> io::Result<f64> {
+        let mut counter = DedupCounter::default();
+        let mut rest = vec![];
+
+        let mut current = read_segment(&mut reader)?;
+        while !current.is_empty() {
+            let next = read_segment(&mut reader)?;
+            self.storage.write_into(
+                &current,
+                &mut chunker,
+                &mut rest,
+                next.is_empty(),
+                &mut counter,
+            )?;
+            current = next;
+        }
+
+        if counter.unique_bytes == 0 {
+            return Ok(1.0);
+        }
+        Ok(counter.total_bytes as f64 / counter.unique_bytes as f64)
+    }
+
+    /// Writes contents of the file with the given name to a file on disk at the given `path`.
+    pub fn write_file_to_disk<P: AsRef<Path>>(&self, name: &str, path: P) -> io::Result<()> {
+        self.write_file_to_disk_with_progress(name, path, |_| {})
+    }
+
+    /// Writes contents of the file with the given name to a file on disk at the given `path`,
+    /// calling `on_progress` with the total number of bytes written so far after each segment.
+    ///
+    /// Encrypted files cannot be written this way, `ErrorKind::PermissionDenied` is returned for them.
+    pub fn write_file_to_disk_with_progress<P, F>(
+        &self,
+        name: &str,
+        path: P,
+        mut on_progress: F,
+    ) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        F: FnMut(u64),
+    {
+        if self.file_layer.is_encrypted(name)? {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+
+        let size = self.file_layer.file_size(name)?;
+        let mut file = std::fs::File::create(path)?;
+        let mut written = 0;
+        while written < size {
+            let end = min(written + SEG_SIZE, size);
+            file.write_all(&self.read_range(name, written, end)?)?;
+            written = end;
+            on_progress(written as u64);
+        }
+        file.flush()
+    }
+
+    /// Reads the byte range `start..end` of the unencrypted file with the given name.
+    fn read_range(&self, name: &str, start: usize, end: usize) -> io::Result<Vec<u8>> {
+        let (extents, first_offset) = self.file_layer.read_range(name, start, end)?;
+        let mut data = self.retrieve_extents(extents, None)?;
+        data.truncate(end - first_offset);
+        data.drain(..start - first_offset);
+        Ok(data)
+    }
+
+    /// Retrieves chunks of the given extents from the storage and joins them, filling holes with zeros.
+    fn retrieve_extents(
+        &self,
+        extents: Vec<Extent<Hash>>,
+        key: Option<&FileKey>,
     ) -> io::Result<Vec<u8>> {
-        let hashes = self.file_layer.read(handle);
-        Ok(self.storage.retrieve(hashes)?.concat())
+        let mut data = vec![];
+        self.retrieve_extents_into(extents, key, &mut data)?;
+        Ok(data)
+    }
+
+    /// Retrieves chunks of the given extents from the storage and appends them to `data`,
+    /// filling holes with zeros.
+    fn retrieve_extents_into(
+        &self,
+        extents: Vec<Extent<Hash>>,
+        key: Option<&FileKey>,
+        data: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        let (hashes, holes) = split_extents(extents);
+        let chunks = self.storage.retrieve(hashes, key)?;
+        join_extents(holes, chunks, data);
+        Ok(())
+    }
+
+    /// Reads all contents of the file from beginning to end, checking that every chunk matches its hash.
+    ///
+    /// Unlike [`read_file_complete`][Self::read_file_complete], which trusts the database,
+    /// it returns `ErrorKind::InvalidData` if any chunk of the file is corrupted.
+    pub fn read_file_verified<C: Chunker>(
+        &mut self,
+        handle: &FileHandle<C>,
+    ) -> io::Result<Vec<u8>> {
+        let (hashes, holes) = split_extents(self.file_layer.read_complete(handle));
+        let chunks = self
+            .storage
+            .retrieve_verified(hashes, handle.key.as_ref())?;
+
+        let mut data = vec![];
+        join_extents(holes, chunks, &mut data);
+        Ok(data)
+    }
+}
+
+/// Splits extents into hashes of their chunks and a layout, in which chunks are `None` and holes are their lengths.
+fn split_extents<Hash: ChunkHash>(extents: Vec<Extent<Hash>>) -> (Vec<Hash>, Vec<Option<usize>>) {
+    let mut hashes = Vec::with_capacity(extents.len());
+    let mut holes = Vec::with_capacity(extents.len());
+    for extent in extents {
+        match extent {
+            Extent::Chunk(hash) => {
+                hashes.push(hash);
+                holes.push(None);
+            }
+            Extent::Hole(length) => holes.push(Some(length)),
+        }
+    }
+    (hashes, holes)
+}
+
+/// Appends retrieved chunks to `data` according to the layout made by [split_extents], filling holes with zeros.
+fn join_extents(holes: Vec<Option<usize>>, chunks: Vec<Vec<u8>>, data: &mut Vec<u8>) {
+    let mut chunks = chunks.into_iter(); // it assumes that all retrieved data segments are in correct order
+    for hole in holes {
+        match hole {
+            Some(length) => data.resize(data.len() + length, 0),
+            None => data.extend(chunks.next().unwrap_or_default()),
+        }
+    }
+}
+
+impl<B, H, Hash> FileSystem<B, H, Hash>
+where
+    B: IterableDatabase<Hash>,
+    H: Hasher<Hash = Hash>,
+    Hash: ChunkHash,
+{
+    /// Returns an iterator over all chunks stored in the database, along with their hashes.
+    pub fn storage_iterator(&self) -> Box<dyn Iterator<Item = (&Hash, &[u8])> + '_> {
+        self.storage.iterator()
+    }
+
+    /// Returns a histogram of sizes of the chunks stored in the database.
+    ///
+    /// Chunk lengths are grouped into buckets of the given size, each key being `len / bucket * bucket`,
+    /// and each value being the number of chunks in the bucket.
+    pub fn chunk_size_histogram(&self, bucket: usize) -> HashMap<usize, u32> {
+        let mut histogram = HashMap::new();
+        for (_, data) in self.storage_iterator() {
+            *histogram.entry(data.len() / bucket * bucket).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Re-hashes every chunk stored in the database with the file system's hasher
+    /// and returns the hashes whose stored data does not match them, i.e. corrupted chunks.
+    pub fn verify_integrity(&mut self) -> io::Result<Vec<Hash>> {
+        Ok(self.storage.verify_integrity())
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl<H, Hash> FileSystem<MmapBase<Hash>, H, Hash>
+where
+    H: Hasher<Hash = Hash>,
+    Hash: ChunkHash,
+{
+    /// Returns offsets in the backing file of all chunks of the file with the given name, in file order.
+    ///
+    /// Shows how scattered the file is across the storage: the less ordered the offsets are,
+    /// the worse the read locality is.
+    pub fn disk_order(&self, name: &str) -> io::Result<Vec<usize>> {
+        let base = self.storage.database();
+        self.file_layer
+            .hashes(name)?
+            .iter()
+            .map(|hash| base.offset_of(hash).ok_or(ErrorKind::NotFound.into()))
+            .collect()
+    }
+}
+
+/// Reads up to [SEG_SIZE] bytes from the reader. Returns less only if the end of the data is reached.
+fn read_segment<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut segment = Vec::with_capacity(SEG_SIZE);
+    reader.take(SEG_SIZE as u64).read_to_end(&mut segment)?;
+    Ok(segment)
+}
+
+/// Failure of a write after some of the data could have been already given to the storage.
+struct PartialWrite {
+    /// Amount of bytes committed to spans of the file before the failure.
+    committed: usize,
+    /// Whether some of the data was accepted, either committed or kept in the handle as its leftover.
+    accepted: bool,
+    error: io::Error,
+}
+
+impl PartialWrite {
+    fn new(committed: usize, accepted: bool, error: io::Error) -> Self {
+        Self {
+            committed,
+            accepted,
+            error,
+        }
+    }
+
+    /// Returns the error if nothing was accepted. Otherwise, keeps the error in the handle
+    /// to be reported by its next write or flush, and returns the amount of committed bytes.
+    fn into_result<C: Chunker>(self, handle: &mut FileHandle<C>) -> io::Result<usize> {
+        if !self.accepted {
+            return Err(self.error);
+        }
+
+        handle.error = Some(self.error);
+        Ok(self.committed)
+    }
+}
+
+/// Database that only counts total and unique amounts of saved data, without keeping the data itself.
+struct DedupCounter<Hash: ChunkHash> {
+    hashes: HashSet<Hash>,
+    total_bytes: usize,
+    unique_bytes: usize,
+}
+
+impl<Hash: ChunkHash> Default for DedupCounter<Hash> {
+    fn default() -> Self {
+        Self {
+            hashes: HashSet::new(),
+            total_bytes: 0,
+            unique_bytes: 0,
+        }
+    }
+}
+
+impl<Hash: ChunkHash> Database<Hash> for DedupCounter<Hash> {
+    fn save(&mut self, segments: Vec<Segment<Hash>>) -> io::Result<()> {
+        for segment in segments {
+            self.total_bytes += segment.data.len();
+            if self.hashes.insert(segment.hash) {
+                self.unique_bytes += segment.data.len();
+            }
+        }
+        Ok(())
+    }
+
+    fn retrieve(&self, _request: Vec<Hash>) -> io::Result<Vec<Vec<u8>>> {
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "data is not kept by the counter",
+        ))
     }
 }
 
@@ -214,3 +1081,38 @@ where
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::base::HashMapBase;
+    use crate::chunkers::FSChunker;
+    use crate::hashers::SimpleHasher;
+    use crate::{FileSystem, SEG_SIZE};
+
+    #[test]
+    fn write_final_produces_same_spans_as_write_and_close() {
+        for size in [10, 4096 * 5, SEG_SIZE, 3 * SEG_SIZE + 50] {
+            let data = (0..size).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+            let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+
+            let mut handle = fs
+                .create_file("closed".to_string(), FSChunker::new(4096), true)
+                .unwrap();
+            fs.write_to_file(&mut handle, &data).unwrap();
+            fs.close_file(handle).unwrap();
+
+            let handle = fs
+                .create_file("final".to_string(), FSChunker::new(4096), true)
+                .unwrap();
+            fs.write_final(handle, &data).unwrap();
+
+            let closed = fs.open_file("closed", FSChunker::new(4096)).unwrap();
+            let last = fs.open_file("final", FSChunker::new(4096)).unwrap();
+            assert_eq!(
+                fs.file_layer.read_complete(&closed),
+                fs.file_layer.read_complete(&last)
+            );
+            assert_eq!(fs.read_file_complete(&last).unwrap(), data);
+        }
+    }
+}
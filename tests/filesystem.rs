@@ -1,9 +1,17 @@
 extern crate chunkfs;
 
-use chunkfs::base::HashMapBase;
-use chunkfs::chunkers::{FSChunker, LeapChunker};
-use chunkfs::hashers::SimpleHasher;
-use chunkfs::{FileOpener, FileSystem};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io;
+use std::io::ErrorKind;
+use std::rc::Rc;
+
+use chunkfs::base::{CompressedBase, HashMapBase, MmapBase, NoDedupBase};
+use chunkfs::chunkers::{FSChunker, LeapChunker, SuperChunker};
+use chunkfs::hashers::{FnHasher, Sha256Hasher, SimpleHasher, XxHasher};
+use chunkfs::{
+    Database, FileKey, FileOpener, FileSystem, Hasher, IterableDatabase, Segment, SharedFileSystem,
+};
 
 const MB: usize = 1024 * 1024;
 
@@ -81,14 +89,1550 @@ fn write_read_big_file_at_once() {
     fs.write_to_file(&mut handle, &data).unwrap();
     fs.close_file(handle).unwrap();
 
-    let mut handle = fs.open_file("file", LeapChunker::default()).unwrap();
+    let handle = fs.open_file("file", LeapChunker::default()).unwrap();
+    assert_eq!(fs.read_file_complete(&handle).unwrap().len(), data.len());
+}
+
+#[test]
+fn write_returns_written_byte_count() {
+    let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+
+    // bytes that are not cut into a chunk yet are not counted until a later write commits them
+    assert_eq!(fs.write_to_file(&mut handle, &[1; 10]).unwrap(), 0);
+    assert_eq!(
+        fs.write_to_file(&mut handle, &vec![1; 2 * MB + 50])
+            .unwrap(),
+        2 * MB
+    );
+    assert_eq!(fs.write_to_file(&mut handle, &[]).unwrap(), 0);
+    assert!(handle.has_unflushed_data());
+    fs.close_file(handle).unwrap();
+}
+
+#[test]
+fn written_byte_count_includes_leftover_of_previous_write() {
+    let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(5000), true)
+        .unwrap();
+
+    assert_eq!(fs.write_to_file(&mut handle, &[1; 4000]).unwrap(), 0);
+    let data = [2; 1000];
+    let written = fs.write_to_file(&mut handle, &data).unwrap();
+    assert_eq!(written, 5000);
+    assert!(written > data.len());
+    assert!(!handle.has_unflushed_data());
+    fs.close_file(handle).unwrap();
+
+    assert_eq!(fs.list_files_with_sizes(), vec![("file".to_string(), 5000)]);
+}
+
+/// Database that runs out of space after a fixed amount of saves.
+#[derive(Default)]
+struct LimitedBase {
+    inner: HashMapBase<Vec<u8>>,
+    saves_left: usize,
+}
+
+impl Database<Vec<u8>> for LimitedBase {
+    fn save(&mut self, segments: Vec<Segment<Vec<u8>>>) -> io::Result<()> {
+        if self.saves_left == 0 {
+            return Err(ErrorKind::OutOfMemory.into());
+        }
+        self.saves_left -= 1;
+        self.inner.save(segments)
+    }
+
+    fn retrieve(&self, request: Vec<Vec<u8>>) -> io::Result<Vec<Vec<u8>>> {
+        self.inner.retrieve(request)
+    }
+}
+
+#[test]
+fn partial_write_returns_committed_byte_count() {
+    let base = LimitedBase {
+        saves_left: 2,
+        ..Default::default()
+    };
+    let mut fs = FileSystem::new(base, SimpleHasher);
+
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(5000), true)
+        .unwrap();
+    // two segments are stored, the leftover after them is not committed
+    let committed = fs.write_to_file(&mut handle, &vec![1; 3 * MB]).unwrap();
+    assert_eq!(committed, 2 * MB / 5000 * 5000);
+    assert_eq!(
+        fs.list_files_with_sizes(),
+        vec![("file".to_string(), committed as u64)]
+    );
+    assert!(handle.has_unflushed_data());
+
+    // the error of the failed segment is reported by the next write
+    let result = fs.write_to_file(&mut handle, &[1; MB]);
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::OutOfMemory);
+    let result = fs.write_to_file(&mut handle, &[1; MB]);
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::OutOfMemory);
+    assert!(fs.close_file(handle).is_err());
+}
+
+#[test]
+fn mmap_base_reads_same_data_as_hashmap_base() {
+    let path = std::env::temp_dir().join("chunkfs_mmap_base_test");
+    let mut mmap_fs = FileSystem::new(MmapBase::new(&path).unwrap(), SimpleHasher);
+    let mut map_fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+
+    let data = (0..3 * MB + 50)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<_>>();
+    for contents in [&data[..], &data[..MB]] {
+        let mut handle = mmap_fs
+            .create_file("file".to_string(), FSChunker::new(4096), true)
+            .unwrap();
+        mmap_fs.write_to_file(&mut handle, contents).unwrap();
+        mmap_fs.close_file(handle).unwrap();
+
+        let mut handle = map_fs
+            .create_file("file".to_string(), FSChunker::new(4096), true)
+            .unwrap();
+        map_fs.write_to_file(&mut handle, contents).unwrap();
+        map_fs.close_file(handle).unwrap();
+
+        let mmap_handle = mmap_fs.open_file("file", FSChunker::new(4096)).unwrap();
+        let map_handle = map_fs.open_file("file", FSChunker::new(4096)).unwrap();
+        let mmap_read = mmap_fs.read_file_complete(&mmap_handle).unwrap();
+        assert_eq!(mmap_read, map_fs.read_file_complete(&map_handle).unwrap());
+        assert_eq!(mmap_read, contents);
+    }
+
+    std::fs::remove_file(path).unwrap();
+}
+
+/// Database whose contents can be inspected after it was moved into the [FileSystem].
+#[derive(Default, Clone)]
+struct SharedBase {
+    segments: Rc<RefCell<HashMap<Vec<u8>, Vec<u8>>>>,
+    syncs: Rc<Cell<usize>>,
+}
+
+impl Database<Vec<u8>> for SharedBase {
+    fn save(&mut self, segments: Vec<Segment<Vec<u8>>>) -> io::Result<()> {
+        let mut map = self.segments.borrow_mut();
+        for segment in segments {
+            map.entry(segment.hash).or_insert(segment.data);
+        }
+        Ok(())
+    }
+
+    fn retrieve(&self, request: Vec<Vec<u8>>) -> io::Result<Vec<Vec<u8>>> {
+        let map = self.segments.borrow();
+        request
+            .iter()
+            .map(|hash| map.get(hash).cloned().ok_or(ErrorKind::NotFound.into()))
+            .collect()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.syncs.set(self.syncs.get() + 1);
+        Ok(())
+    }
+}
+
+#[test]
+fn encrypted_file_is_read_only_with_correct_key() {
+    let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+
+    let key = FileKey::new([7; 32]);
+    let mut handle = fs
+        .create_file_encrypted("file".to_string(), FSChunker::new(4096), key.clone(), true)
+        .unwrap();
+    let data = (0..MB + 50).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    fs.write_to_file(&mut handle, &data).unwrap();
+    fs.close_file(handle).unwrap();
+
+    let result = fs.open_file("file", FSChunker::new(4096));
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::PermissionDenied);
+
+    let wrong_key = FileKey::new([8; 32]);
+    let handle = fs
+        .open_file_encrypted("file", FSChunker::new(4096), wrong_key)
+        .unwrap();
+    let result = fs.read_file_complete(&handle);
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+
+    let mut handle = fs
+        .open_file_encrypted("file", FSChunker::new(4096), key)
+        .unwrap();
+    assert_eq!(fs.read_file_complete(&handle).unwrap(), data);
+    assert_eq!(fs.read_from_file(&mut handle).unwrap(), data[..MB]);
+}
+
+#[test]
+fn encrypted_chunks_are_deduplicated_only_within_file() {
+    let base = SharedBase::default();
+    let mut fs = FileSystem::new(base.clone(), SimpleHasher);
+
+    let data = vec![1; 4096 * 10];
+    for (name, key) in [("first", [1; 32]), ("second", [2; 32])] {
+        let mut handle = fs
+            .create_file_encrypted(
+                name.to_string(),
+                FSChunker::new(4096),
+                FileKey::new(key),
+                true,
+            )
+            .unwrap();
+        fs.write_to_file(&mut handle, &data).unwrap();
+        fs.close_file(handle).unwrap();
+    }
+    assert_eq!(base.segments.borrow().len(), 2);
+
+    let handle = fs
+        .open_file_encrypted("second", FSChunker::new(4096), FileKey::new([2; 32]))
+        .unwrap();
+    assert_eq!(fs.read_file_complete(&handle).unwrap(), data);
+}
+
+#[test]
+fn shared_fs_reads_concurrently_with_writer() {
+    let fs = SharedFileSystem::new(FileSystem::new(HashMapBase::default(), SimpleHasher));
+
+    let data = (0..2 * MB + 50)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<_>>();
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_to_file(&mut handle, &data).unwrap();
+    fs.close_file(handle).unwrap();
+
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            for i in 0..5 {
+                let mut handle = fs
+                    .create_file(format!("other{i}"), FSChunker::new(4096), true)
+                    .unwrap();
+                fs.write_to_file(&mut handle, &[i; MB]).unwrap();
+                fs.close_file(handle).unwrap();
+            }
+        });
+
+        for _ in 0..4 {
+            scope.spawn(|| {
+                for _ in 0..5 {
+                    let mut handle = fs.open_file("file", FSChunker::new(4096)).unwrap();
+                    assert_eq!(fs.read_file_complete(&handle).unwrap(), data);
+                    assert_eq!(fs.read_from_file(&mut handle).unwrap(), data[..MB]);
+                }
+            });
+        }
+    });
+
+    let fs = fs.into_inner();
+    for i in 0..5 {
+        let handle = fs
+            .open_file(&format!("other{i}"), FSChunker::new(4096))
+            .unwrap();
+        assert_eq!(fs.read_file_complete(&handle).unwrap(), [i; MB]);
+    }
+}
+
+/// Database that corrupts data of segments starting with `bad_byte` when saving them.
+struct FaultyBase {
+    segments: HashMap<Vec<u8>, Vec<u8>>,
+    bad_byte: u8,
+}
+
+impl Database<Vec<u8>> for FaultyBase {
+    fn save(&mut self, segments: Vec<Segment<Vec<u8>>>) -> io::Result<()> {
+        for mut segment in segments {
+            if segment.data.first() == Some(&self.bad_byte) {
+                segment.data[0] = !self.bad_byte;
+            }
+            self.segments.entry(segment.hash).or_insert(segment.data);
+        }
+        Ok(())
+    }
+
+    fn retrieve(&self, request: Vec<Vec<u8>>) -> io::Result<Vec<Vec<u8>>> {
+        request
+            .iter()
+            .map(|hash| {
+                self.segments
+                    .get(hash)
+                    .cloned()
+                    .ok_or(ErrorKind::NotFound.into())
+            })
+            .collect()
+    }
+}
+
+impl IterableDatabase<Vec<u8>> for FaultyBase {
+    fn iterator(&self) -> Box<dyn Iterator<Item = (&Vec<u8>, &[u8])> + '_> {
+        Box::new(
+            self.segments
+                .iter()
+                .map(|(hash, data)| (hash, data.as_slice())),
+        )
+    }
+}
+
+#[test]
+fn verify_integrity_reports_corrupted_chunks() {
+    let base = FaultyBase {
+        segments: HashMap::new(),
+        bad_byte: 2,
+    };
+    let mut fs = FileSystem::new(base, SimpleHasher);
+
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    for byte in 1..=3 {
+        fs.write_to_file(&mut handle, &[byte; 4096]).unwrap();
+    }
+    fs.close_file(handle).unwrap();
+
+    assert_eq!(fs.verify_integrity().unwrap(), vec![vec![2; 4096]]);
+}
+
+#[test]
+fn verify_integrity_of_intact_database_is_empty() {
+    let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_to_file(&mut handle, &vec![1; MB + 50]).unwrap();
+    fs.close_file(handle).unwrap();
+
+    assert!(fs.verify_integrity().unwrap().is_empty());
+}
+
+#[test]
+fn write_read_with_xx_hasher() {
+    let mut fs = FileSystem::new(HashMapBase::default(), XxHasher);
+
+    let mut handle = fs
+        .create_file("file".to_string(), LeapChunker::default(), true)
+        .unwrap();
+    let data = (0..2 * MB + 50)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<_>>();
+    fs.write_to_file(&mut handle, &data).unwrap();
+    fs.close_file(handle).unwrap();
+
+    let handle = fs.open_file("file", LeapChunker::default()).unwrap();
+    assert_eq!(fs.read_file_complete(&handle).unwrap(), data);
+}
+
+#[test]
+fn storage_iterator_over_mmap_base() {
+    let path = std::env::temp_dir().join("chunkfs_storage_iterator_test");
+    let mut fs = FileSystem::new(MmapBase::new(&path).unwrap(), SimpleHasher);
+
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    for byte in 0..10 {
+        fs.write_to_file(&mut handle, &[byte; 4096 * 3]).unwrap();
+    }
+    fs.write_to_file(&mut handle, &[10; 100]).unwrap();
+    fs.close_file(handle).unwrap();
+
+    let mut chunks = fs
+        .storage_iterator()
+        .map(|(hash, data)| {
+            assert_eq!(hash, data);
+            data.to_vec()
+        })
+        .collect::<Vec<_>>();
+    chunks.sort();
+
+    let mut expected = (0..10).map(|byte| vec![byte; 4096]).collect::<Vec<_>>();
+    expected.push(vec![10; 100]);
+    assert_eq!(chunks, expected);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+fn fs_with_file(data: &[u8]) -> FileSystem<HashMapBase<Vec<u8>>, SimpleHasher, Vec<u8>> {
+    let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_to_file(&mut handle, data).unwrap();
+    fs.close_file(handle).unwrap();
+    fs
+}
+
+fn read_file(fs: &FileSystem<HashMapBase<Vec<u8>>, SimpleHasher, Vec<u8>>, name: &str) -> Vec<u8> {
+    let handle = fs.open_file(name, FSChunker::new(4096)).unwrap();
+    fs.read_file_complete(&handle).unwrap()
+}
+
+#[test]
+fn truncate_file_in_the_middle_of_chunk() {
+    let data = (0..4096 * 3).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let mut fs = fs_with_file(&data);
+
+    fs.truncate_file("file", 5000).unwrap();
+    assert_eq!(read_file(&fs, "file"), data[..5000]);
+
+    fs.truncate_file("file", 10).unwrap();
+    assert_eq!(read_file(&fs, "file"), data[..10]);
+
+    fs.truncate_file("file", 0).unwrap();
+    assert!(read_file(&fs, "file").is_empty());
+}
+
+#[test]
+fn failed_truncate_keeps_file() {
+    let base = LimitedBase {
+        saves_left: 1,
+        ..Default::default()
+    };
+    let mut fs = FileSystem::new(base, SimpleHasher);
+    let data = (0..4096 * 3).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_final(handle, &data).unwrap();
+
+    let error = fs.truncate_file("file", 5000).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+    let handle = fs.open_file("file", FSChunker::new(4096)).unwrap();
+    assert_eq!(fs.read_file_complete(&handle).unwrap(), data);
+}
+
+#[test]
+fn truncate_file_on_chunk_boundary() {
+    let data = (0..4096 * 3 + 100)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<_>>();
+    let mut fs = fs_with_file(&data);
+
+    fs.truncate_file("file", 8192).unwrap();
+    assert_eq!(read_file(&fs, "file"), data[..8192]);
+
+    let mut handle = fs.open_file("file", FSChunker::new(4096)).unwrap();
+    assert_eq!(fs.read_from_file(&mut handle).unwrap(), data[..8192]);
+}
+
+#[test]
+fn truncate_file_grows_it_with_zeros() {
+    let data = vec![1; 5000];
+    let mut fs = fs_with_file(&data);
+
+    fs.truncate_file("file", (2 * MB + 5000) as u64).unwrap();
+
+    let read = read_file(&fs, "file");
+    assert_eq!(read.len(), 2 * MB + 5000);
+    assert_eq!(read[..5000], data);
+    assert!(read[5000..].iter().all(|&byte| byte == 0));
+
+    let result = fs.truncate_file("missing", 0);
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn disk_order_reports_chunk_offsets() {
+    let path = std::env::temp_dir().join("chunkfs_disk_order_test");
+    let mut fs = FileSystem::new(MmapBase::new(&path).unwrap(), SimpleHasher);
+
+    let chunks = (0..5).map(|byte| vec![byte; 4096]).collect::<Vec<_>>();
+    for (name, order) in [("forward", [0, 1, 2, 3, 4]), ("backward", [4, 3, 2, 1, 0])] {
+        let mut handle = fs
+            .create_file(name.to_string(), FSChunker::new(4096), true)
+            .unwrap();
+        for i in order {
+            fs.write_to_file(&mut handle, &chunks[i]).unwrap();
+        }
+        fs.close_file(handle).unwrap();
+    }
+
+    let record_offsets = (0..5).map(|i| i * (4096 + 8) + 8).collect::<Vec<_>>();
+    assert_eq!(fs.disk_order("forward").unwrap(), record_offsets);
+    assert_eq!(
+        fs.disk_order("backward").unwrap(),
+        record_offsets.into_iter().rev().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        fs.disk_order("missing").unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn chunk_size_histogram_of_fixed_size_chunks() {
+    let data = (0..MB).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let fs = fs_with_file(&data);
+
+    let histogram = fs.chunk_size_histogram(1024);
+    assert_eq!(histogram.len(), 1);
+    assert_eq!(histogram[&4096], 251);
+
+    let fs = fs_with_file(&data[..4096 + 100]);
+    let histogram = fs.chunk_size_histogram(1000);
+    assert_eq!(histogram, HashMap::from([(4000, 1), (0, 1)]));
+}
+
+#[test]
+fn no_dedup_base_keeps_duplicate_chunks() {
+    let data = (0..MB).map(|i| (i % 256) as u8).collect::<Vec<_>>();
+
+    let mut fs = FileSystem::new(NoDedupBase::default(), SimpleHasher);
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_to_file(&mut handle, &data).unwrap();
+    fs.write_to_file(&mut handle, &data).unwrap();
+    fs.close_file(handle).unwrap();
+
+    let stored_size = fs
+        .storage_iterator()
+        .map(|(_, data)| data.len())
+        .sum::<usize>();
+    assert_eq!(stored_size, 2 * MB);
+
+    let handle = fs.open_file("file", FSChunker::new(4096)).unwrap();
+    assert_eq!(fs.read_file_complete(&handle).unwrap(), data.repeat(2));
+
+    let fs = fs_with_file(&data.repeat(2));
+    let stored_size = fs
+        .storage_iterator()
+        .map(|(_, data)| data.len())
+        .sum::<usize>();
+    assert_eq!(stored_size, 4096);
+}
+
+#[test]
+fn sparse_write_leaves_hole_of_zeros() {
+    let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_to_file(&mut handle, &[1; 5000]).unwrap();
+    assert_eq!(
+        fs.write_sparse(&mut handle, 20000, &[2; 5000]).unwrap(),
+        5000
+    );
+    fs.write_to_file(&mut handle, &[3; 100]).unwrap();
+    fs.close_file(handle).unwrap();
+
+    let mut expected = vec![1; 5000];
+    expected.extend(vec![0; 15000]);
+    expected.extend(vec![2; 5000]);
+    expected.extend(vec![3; 100]);
+    assert_eq!(read_file(&fs, "file"), expected);
+}
+
+#[test]
+fn sparse_writes_can_fill_hole() {
+    let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_sparse(&mut handle, 0, &[1; 100]).unwrap();
+    fs.write_sparse(&mut handle, MB * 2, &[3; 100]).unwrap();
+
+    fs.write_sparse(&mut handle, MB, &[2; 10000]).unwrap();
+    let mut expected = vec![1; 100];
+    expected.extend(vec![0; MB - 100]);
+    expected.extend(vec![2; 10000]);
+    expected.extend(vec![0; MB - 10000]);
+    expected.extend(vec![3; 100]);
+    assert_eq!(fs.read_file_complete(&handle).unwrap(), expected);
+
+    fs.write_sparse(&mut handle, 100, &vec![4; MB - 100])
+        .unwrap();
+    fs.write_sparse(&mut handle, MB + 10000, &vec![5; MB - 10000])
+        .unwrap();
+    fs.close_file(handle).unwrap();
+
+    let mut expected = vec![1; 100];
+    expected.extend(vec![4; MB - 100]);
+    expected.extend(vec![2; 10000]);
+    expected.extend(vec![5; MB - 10000]);
+    expected.extend(vec![3; 100]);
+    assert_eq!(read_file(&fs, "file"), expected);
+}
+
+#[test]
+fn sparse_write_over_stored_data_fails() {
+    let mut fs = fs_with_file(&[1; 10000]);
+    let mut handle = fs.open_file("file", FSChunker::new(4096)).unwrap();
+
+    let error = fs.write_sparse(&mut handle, 5000, &[2; 100]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert_eq!(read_file(&fs, "file"), vec![1; 10000]);
+}
+
+#[test]
+fn rejected_sparse_write_stores_nothing() {
+    let mut fs = fs_with_file(&[1; 10000]);
+    let mut handle = fs.open_file("file", FSChunker::new(4096)).unwrap();
+    fs.write_to_file(&mut handle, &[2; 1000]).unwrap();
+    let chunk_count = fs.storage_iterator().count();
+
+    for offset in [5000, 10500] {
+        let error = fs
+            .write_sparse(&mut handle, offset, &[3; 5000])
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(handle.has_unflushed_data());
+        assert_eq!(fs.storage_iterator().count(), chunk_count);
+    }
+
+    fs.write_sparse(&mut handle, 11000, &[3; 100]).unwrap();
+    fs.close_file(handle).unwrap();
+    let mut expected = vec![1; 10000];
+    expected.extend([2; 1000]);
+    expected.extend([3; 100]);
+    assert_eq!(read_file(&fs, "file"), expected);
+}
+
+#[test]
+fn truncate_file_shortens_hole() {
+    let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_sparse(&mut handle, 10000, &[1; 100]).unwrap();
+    fs.close_file(handle).unwrap();
+
+    fs.truncate_file("file", 5000).unwrap();
+    assert_eq!(read_file(&fs, "file"), vec![0; 5000]);
+}
+
+#[test]
+fn list_files_with_sizes_reports_logical_sizes() {
+    let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+    for (name, size) in [("empty", 0), ("small", 100), ("big", 3 * MB + 5)] {
+        let mut handle = fs
+            .create_file(name.to_string(), FSChunker::new(4096), true)
+            .unwrap();
+        fs.write_to_file(&mut handle, &vec![1; size]).unwrap();
+        fs.close_file(handle).unwrap();
+    }
+
+    let mut files = fs.list_files_with_sizes();
+    files.sort();
     assert_eq!(
-        fs.read_file_complete(&mut handle).unwrap().len(),
-        data.len()
+        files,
+        vec![
+            ("big".to_string(), (3 * MB + 5) as u64),
+            ("empty".to_string(), 0),
+            ("small".to_string(), 100),
+        ]
     );
+    for (name, size) in files {
+        let handle = fs.open_file(&name, FSChunker::new(4096)).unwrap();
+        assert_eq!(fs.read_file_complete(&handle).unwrap().len() as u64, size);
+    }
+}
+
+#[test]
+fn read_into_reuses_buffer() {
+    let data = (0..3 * MB + 500)
+        .map(|i| (i % 253) as u8)
+        .collect::<Vec<_>>();
+    let fs = fs_with_file(&data);
+    let mut handle = fs.open_file("file", FSChunker::new(4096)).unwrap();
+
+    let mut buf = Vec::with_capacity(2 * MB);
+    let capacity = buf.capacity();
+    let mut read = vec![];
+    loop {
+        let count = fs.read_into(&mut handle, &mut buf).unwrap();
+        if count == 0 {
+            break;
+        }
+        assert_eq!(count, buf.len());
+        read.extend_from_slice(&buf);
+    }
+
+    assert_eq!(buf.capacity(), capacity);
+    assert_eq!(read, data);
+}
+
+#[test]
+fn reference_count_distribution_counts_shared_chunks() {
+    let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+    let shared = vec![1; 4096];
+    let mut first = shared.clone();
+    first.extend(vec![2; 4096]);
+    let mut second = shared.clone();
+    second.extend(vec![3; 4096]);
+
+    for (name, data) in [("first", first), ("second", second)] {
+        let mut handle = fs
+            .create_file(name.to_string(), FSChunker::new(4096), true)
+            .unwrap();
+        fs.write_to_file(&mut handle, &data).unwrap();
+        fs.close_file(handle).unwrap();
+    }
+
+    let distribution = fs.reference_count_distribution();
+    assert_eq!(distribution, HashMap::from([(1, 2), (2, 1)]));
+}
+
+#[test]
+fn open_handles_are_counted() {
+    let mut fs = fs_with_file(&[1; 100]);
+    assert_eq!(fs.open_handles(), 0);
+
+    let reader = fs.open_file("file", FSChunker::new(4096)).unwrap();
+    let mut writer = fs
+        .create_file("new".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    assert_eq!(fs.open_handles(), 2);
+
+    fs.write_to_file(&mut writer, &[2; 100]).unwrap();
+    assert!(writer.has_unflushed_data());
+    fs.close_file(writer).unwrap();
+    drop(reader);
+    assert_eq!(fs.open_handles(), 0);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "dropped without closing")]
+fn dropping_handle_with_unflushed_data_panics_in_debug() {
+    let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_to_file(&mut handle, &[1; 100]).unwrap();
+    drop(handle);
+}
+
+#[test]
+fn flush_file_keeps_handle_usable() {
+    let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+
+    fs.write_to_file(&mut handle, &[1; 5000]).unwrap();
+    fs.flush_file(&mut handle).unwrap();
+    assert!(!handle.has_unflushed_data());
+    assert_eq!(read_file(&fs, "file"), vec![1; 5000]);
+
+    fs.write_to_file(&mut handle, &[2; 5000]).unwrap();
+    fs.close_file(handle).unwrap();
+
+    let mut expected = vec![1; 5000];
+    expected.extend(vec![2; 5000]);
+    assert_eq!(read_file(&fs, "file"), expected);
+}
+
+#[test]
+fn read_concat_crosses_file_boundaries() {
+    let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+    let mut all = vec![];
+    for (name, size) in [("a", 5000), ("b", 300), ("c", 9000)] {
+        let data = (0..size)
+            .map(|i| ((i + size) % 199) as u8)
+            .collect::<Vec<_>>();
+        let mut handle = fs
+            .create_file(name.to_string(), FSChunker::new(4096), true)
+            .unwrap();
+        fs.write_to_file(&mut handle, &data).unwrap();
+        fs.close_file(handle).unwrap();
+        all.extend(data);
+    }
+
+    let names = ["a", "b", "c"];
+    assert_eq!(
+        fs.read_concat(&names, 4000, 6000).unwrap(),
+        all[4000..10000]
+    );
+    assert_eq!(fs.read_concat(&names, 5000, 300).unwrap(), all[5000..5300]);
+    assert_eq!(fs.read_concat(&names, 14000, 1000).unwrap(), all[14000..]);
+    assert!(fs.read_concat(&names, 20000, 10).unwrap().is_empty());
+    assert_eq!(fs.read_concat(&names, 0, usize::MAX).unwrap(), all);
+    assert_eq!(
+        fs.read_concat(&names, 14000, usize::MAX).unwrap(),
+        all[14000..]
+    );
+    assert!(fs
+        .read_concat(&names, usize::MAX, usize::MAX)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn total_write_measurements_sum_closed_handles() {
+    let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+    assert_eq!(fs.total_write_measurements(), Default::default());
+
+    let mut handle = fs
+        .create_file("first".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_to_file(&mut handle, &vec![1; 2 * MB + 10])
+        .unwrap();
+    let mut expected = fs.close_file(handle).unwrap();
+
+    let handle = fs
+        .create_file("second".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    expected += fs.write_final(handle, &vec![2; MB]).unwrap();
+
+    assert_eq!(fs.total_write_measurements(), expected);
+}
+
+#[test]
+fn write_file_to_disk_reports_progress() {
+    let data = (0..2 * MB + 300)
+        .map(|i| (i % 241) as u8)
+        .collect::<Vec<_>>();
+    let fs = fs_with_file(&data);
+    let path = std::env::temp_dir().join("chunkfs_write_file_to_disk_test");
+
+    let mut progress = vec![];
+    fs.write_file_to_disk_with_progress("file", &path, |written| progress.push(written))
+        .unwrap();
+
+    assert_eq!(progress.last().copied(), Some(data.len() as u64));
+    assert!(progress.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn estimate_dedup_matches_actual_ratio() {
+    let block = (0..40000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let mut data = vec![];
+    for _ in 0..60 {
+        data.extend_from_slice(&block);
+    }
+    data.extend(vec![7; 1000]);
+
+    let mut fs = FileSystem::new(HashMapBase::default(), XxHasher);
+    let estimate = fs
+        .estimate_dedup(FSChunker::new(4096), data.as_slice())
+        .unwrap();
+    assert_eq!(fs.storage_iterator().count(), 0);
+
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_to_file(&mut handle, &data).unwrap();
+    fs.close_file(handle).unwrap();
+    let stored = fs
+        .storage_iterator()
+        .map(|(_, chunk)| chunk.len())
+        .sum::<usize>();
+
+    assert!(estimate > 1.0);
+    assert_eq!(estimate, data.len() as f64 / stored as f64);
+}
+
+#[test]
+fn file_with_missing_chunk_is_incomplete() {
+    let base = SharedBase::default();
+    let mut fs = FileSystem::new(base.clone(), SimpleHasher);
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_to_file(&mut handle, &[1; 10000]).unwrap();
+    fs.close_file(handle).unwrap();
+    assert!(fs.file_is_complete("file").unwrap());
+
+    base.segments.borrow_mut().remove(&vec![1; 4096]);
+    assert!(fs.file_exists("file"));
+    assert!(!fs.file_is_complete("file").unwrap());
+    assert_eq!(
+        fs.file_is_complete("missing").unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn append_with_different_chunker() {
+    let first = (0..3 * MB)
+        .map(|i| (i * 13 % 255) as u8)
+        .collect::<Vec<_>>();
+    let second = (0..MB + 777)
+        .map(|i| (i * 7 % 253) as u8)
+        .collect::<Vec<_>>();
+    let mut fs = fs_with_file(&first);
+    assert_eq!(fs.chunker_of("file").as_deref(), Some("FSChunker"));
+
+    let mut handle = fs.open_file("file", SuperChunker::new()).unwrap();
+    fs.write_to_file(&mut handle, &second).unwrap();
+    fs.close_file(handle).unwrap();
+    assert_eq!(fs.chunker_of("file").as_deref(), Some("FSChunker"));
+    assert_eq!(fs.chunker_of("missing"), None);
+
+    let mut expected = first;
+    expected.extend(second);
+    assert_eq!(read_file(&fs, "file"), expected);
+
+    let mut handle = fs.open_file("file", FSChunker::new(4096)).unwrap();
+    let mut read = vec![];
+    let mut buf = vec![];
+    while fs.read_into(&mut handle, &mut buf).unwrap() > 0 {
+        read.extend_from_slice(&buf);
+    }
+    assert_eq!(read, expected);
+}
+
+#[test]
+fn copy_file_shares_chunks() {
+    let data = (0..2 * MB + 100)
+        .map(|i| (i % 239) as u8)
+        .collect::<Vec<_>>();
+    let mut fs = fs_with_file(&data);
+    let chunk_count = fs.storage_iterator().count();
+
+    fs.copy_file("file", "copy").unwrap();
+    assert_eq!(fs.storage_iterator().count(), chunk_count);
+    assert_eq!(read_file(&fs, "copy"), data);
+    assert_eq!(read_file(&fs, "file"), data);
+
+    let error = fs.copy_file("missing", "other").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    let error = fs.copy_file("file", "copy").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+}
+
+#[test]
+fn write_read_with_closure_hasher() {
+    let hasher = FnHasher::new(|data: &[u8]| {
+        data.iter().fold(0u64, |hash, &byte| {
+            hash.wrapping_mul(31).wrapping_add(byte as u64)
+        })
+    });
+    let mut fs = FileSystem::new(HashMapBase::default(), hasher);
+    let data = (0..MB + 10).map(|i| (i % 233) as u8).collect::<Vec<_>>();
+
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_to_file(&mut handle, &data).unwrap();
+    fs.close_file(handle).unwrap();
+
+    let handle = fs.open_file("file", FSChunker::new(4096)).unwrap();
+    assert_eq!(fs.read_file_complete(&handle).unwrap(), data);
+}
+
+#[test]
+fn shared_chunk_bytes_of_two_files() {
+    let mut fs = FileSystem::new(HashMapBase::default(), XxHasher);
+    let mut first = vec![1; MB];
+    first.extend(vec![2; MB]);
+    first.extend(vec![1; MB]);
+    let mut second = vec![3; MB];
+    second.extend(vec![1; MB]);
+
+    for (name, data) in [("first", first), ("second", second)] {
+        let handle = fs
+            .create_file(name.to_string(), FSChunker::new(MB), true)
+            .unwrap();
+        fs.write_final(handle, &data).unwrap();
+    }
+
+    assert_eq!(fs.shared_chunk_bytes("first", "second").unwrap(), MB);
+    assert_eq!(fs.shared_chunk_bytes("first", "first").unwrap(), 2 * MB);
+    let error = fs.shared_chunk_bytes("first", "missing").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn stream_spans_do_not_depend_on_read_buffer_size() {
+    let data = (0..3 * MB + 12345)
+        .map(|i| ((i * 31) ^ (i >> 9)) as u8)
+        .collect::<Vec<_>>();
+    let mut fs = FileSystem::new(HashMapBase::default(), XxHasher);
+
+    let mut handle = fs
+        .create_file("file".to_string(), SuperChunker::new(), true)
+        .unwrap();
+    let written = fs.write_from_stream(&mut handle, data.as_slice()).unwrap();
+    assert_eq!(
+        fs.list_files_with_sizes(),
+        vec![("file".to_string(), written as u64)]
+    );
+    fs.close_file(handle).unwrap();
+    let chunk_count = fs.storage_iterator().count();
+
+    for read_buf_size in [1000, 3 * MB / 2, 4 * MB] {
+        let name = format!("file{read_buf_size}");
+        let mut handle = fs
+            .create_file(name.clone(), SuperChunker::new(), true)
+            .unwrap();
+        fs.write_from_stream_buffered(&mut handle, data.as_slice(), read_buf_size)
+            .unwrap();
+        fs.close_file(handle).unwrap();
+
+        assert_eq!(fs.storage_iterator().count(), chunk_count);
+        assert_eq!(
+            fs.shared_chunk_bytes("file", &name).unwrap(),
+            fs.shared_chunk_bytes("file", "file").unwrap()
+        );
+        let handle = fs.open_file(&name, SuperChunker::new()).unwrap();
+        assert_eq!(fs.read_file_complete(&handle).unwrap(), data);
+    }
+}
+
+#[test]
+fn file_manifest_covers_file() {
+    let data = (0..2 * MB + 999)
+        .map(|i| (i % 229) as u8)
+        .collect::<Vec<_>>();
+    let mut fs = FileSystem::new(HashMapBase::default(), XxHasher);
+    let handle = fs
+        .create_file("file".to_string(), LeapChunker::default(), true)
+        .unwrap();
+    fs.write_final(handle, &data).unwrap();
+
+    let manifest = fs.file_manifest("file").unwrap();
+    let mut offset = 0;
+    for (hash, length) in manifest {
+        assert_eq!(hash, XxHasher.hash(&data[offset..offset + length]));
+        offset += length;
+    }
+    assert_eq!(offset, data.len());
+}
+
+#[test]
+fn absorb_merges_chunks_and_files() {
+    let mut first = FileSystem::new(HashMapBase::default(), XxHasher);
+    let mut second = FileSystem::new(HashMapBase::default(), XxHasher);
+    let mut shared = vec![1; 4096];
+    shared.extend(vec![2; 4096]);
+    let mut other = vec![1; 4096];
+    other.extend(vec![3; 4096]);
+
+    let handle = first
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    first.write_final(handle, &shared).unwrap();
+    for (name, data) in [("file", &other), ("other", &shared)] {
+        let handle = second
+            .create_file(name.to_string(), FSChunker::new(4096), true)
+            .unwrap();
+        second.write_final(handle, data).unwrap();
+    }
+
+    let mut names = first.absorb(&second).unwrap();
+    names.sort();
+    assert_eq!(names, vec!["file (1)".to_string(), "other".to_string()]);
+    assert_eq!(first.storage_iterator().count(), 3);
+
+    for (name, data) in [("file", &shared), ("file (1)", &other), ("other", &shared)] {
+        let handle = first.open_file(name, FSChunker::new(4096)).unwrap();
+        assert_eq!(&first.read_file_complete(&handle).unwrap(), data);
+    }
+}
+
+#[test]
+fn compression_adds_to_dedup_ratio() {
+    let block = (0..MB).map(|i| (i / 100 % 4) as u8).collect::<Vec<_>>();
+    let mut data = block.clone();
+    data.extend(&block);
+
+    let mut plain = FileSystem::new(HashMapBase::default(), XxHasher);
+    let mut compressed = FileSystem::new(CompressedBase::new(HashMapBase::default()), XxHasher);
+    assert_eq!(plain.compression_ratio(), None);
+
+    let handle = plain
+        .create_file("file".to_string(), FSChunker::new(MB), true)
+        .unwrap();
+    plain.write_final(handle, &data).unwrap();
+    let handle = compressed
+        .create_file("file".to_string(), FSChunker::new(MB), true)
+        .unwrap();
+    compressed.write_final(handle, &data).unwrap();
+
+    let dedup_ratio = plain.compression_ratio().unwrap();
+    assert_eq!(dedup_ratio, 2.0);
+    assert!(compressed.compression_ratio().unwrap() > 10.0 * dedup_ratio);
+}
+
+#[test]
+fn list_files_with_prefix_filters_names() {
+    let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+    for name in [
+        "backups/2023/a",
+        "backups/2024/a",
+        "backups/2024/b",
+        "logs/2024/a",
+    ] {
+        let handle = fs
+            .create_file(name.to_string(), FSChunker::new(4096), true)
+            .unwrap();
+        fs.close_file(handle).unwrap();
+    }
+
+    let mut files = fs.list_files_with_prefix("backups/2024/");
+    files.sort();
+    assert_eq!(files, vec!["backups/2024/a", "backups/2024/b"]);
+    assert_eq!(fs.list_files_with_prefix("logs/").len(), 1);
+    assert_eq!(fs.list_files_with_prefix("").len(), 4);
+    assert!(fs.list_files_with_prefix("tmp/").is_empty());
+}
+
+#[test]
+fn verified_read_detects_corrupted_chunk() {
+    let base = FaultyBase {
+        segments: HashMap::new(),
+        bad_byte: 2,
+    };
+    let mut fs = FileSystem::new(base, SimpleHasher);
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_to_file(&mut handle, &[1; 4096]).unwrap();
+    fs.close_file(handle).unwrap();
+
+    let handle = fs.open_file("file", FSChunker::new(4096)).unwrap();
+    assert_eq!(fs.read_file_verified(&handle).unwrap(), vec![1; 4096]);
+
+    let mut handle = fs
+        .create_file("corrupted".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_to_file(&mut handle, &[2; 4096]).unwrap();
+    fs.close_file(handle).unwrap();
+
+    let handle = fs.open_file("corrupted", FSChunker::new(4096)).unwrap();
+    assert_ne!(fs.read_file_complete(&handle).unwrap(), vec![2; 4096]);
+    let error = fs.read_file_verified(&handle).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn reuse_map_reports_most_referenced_chunk() {
+    let mut data = vec![7; 4096 * 5];
+    data.extend(vec![1; 4096]);
+    data.extend(vec![2; 1000]);
+    let mut fs = fs_with_file(&data);
+
+    let map = fs.export_reuse_map();
+    assert_eq!(map.len(), 3);
+    assert_eq!(map[0].0, vec![7; 4096]);
+    assert_eq!(map[0].1, 4096);
+    assert_eq!(map[0].2, 5);
+    assert!(map.contains(&(vec![2; 1000], 1000, 1)));
+
+    fs.copy_file("file", "copy").unwrap();
+    assert_eq!(fs.export_reuse_map()[0].2, 10);
+}
+
+#[test]
+fn write_from_stream_commits_spans_per_segment() {
+    struct FailingReader {
+        data: Vec<u8>,
+        position: usize,
+    }
+
+    impl io::Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.position == self.data.len() {
+                return Err(ErrorKind::BrokenPipe.into());
+            }
+            let read = buf.len().min(self.data.len() - self.position);
+            buf[..read].copy_from_slice(&self.data[self.position..self.position + read]);
+            self.position += read;
+            Ok(read)
+        }
+    }
+
+    let data = (0..5 * MB).map(|i| (i % 233) as u8).collect::<Vec<_>>();
+    let reader = FailingReader {
+        data: data.clone(),
+        position: 0,
+    };
+
+    let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    let error = fs.write_from_stream(&mut handle, reader).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::BrokenPipe);
+
+    // every segment read before the failure is already in the file, in order
+    fs.close_file(handle).unwrap();
+    assert_eq!(read_file(&fs, "file"), data);
+}
+
+#[test]
+fn empty_file_and_empty_database() {
+    let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+    assert_eq!(fs.compression_ratio(), None);
+    assert!(fs.reference_count_distribution().is_empty());
+    assert!(fs.export_reuse_map().is_empty());
+    assert!(fs.verify_integrity().unwrap().is_empty());
+    assert_eq!(fs.storage_iterator().count(), 0);
+
+    let mut handle = fs
+        .create_file("empty".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    assert_eq!(fs.write_to_file(&mut handle, &[]).unwrap(), 0);
+    assert!(!handle.has_unflushed_data());
+    fs.close_file(handle).unwrap();
+
+    let handle = fs.open_file("empty", FSChunker::new(4096)).unwrap();
+    assert!(fs.read_file_complete(&handle).unwrap().is_empty());
+    assert!(fs.read_file_verified(&handle).unwrap().is_empty());
+    assert!(fs.file_manifest("empty").unwrap().is_empty());
+    assert_eq!(fs.list_files_with_sizes(), vec![("empty".to_string(), 0)]);
+    assert!(fs.file_is_complete("empty").unwrap());
+    assert_eq!(fs.storage_iterator().count(), 0);
+    assert_eq!(fs.compression_ratio(), None);
+
+    let handle = fs
+        .create_file("final".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_final(handle, &[]).unwrap();
+    assert!(read_file(&fs, "final").is_empty());
+}
+
+#[test]
+fn sync_all_reaches_database() {
+    let base = SharedBase::default();
+    let mut fs = FileSystem::new(CompressedBase::new(base.clone()), SimpleHasher);
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_to_file(&mut handle, &[1; 10000]).unwrap();
+    fs.close_file(handle).unwrap();
+
+    assert_eq!(base.syncs.get(), 0);
+    fs.sync_all().unwrap();
+    assert_eq!(base.syncs.get(), 1);
+
+    let path = std::env::temp_dir().join("chunkfs_sync_all_test");
+    let mut fs = FileSystem::new(MmapBase::new(&path).unwrap(), SimpleHasher);
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_to_file(&mut handle, &[1; 10000]).unwrap();
+    fs.close_file(handle).unwrap();
+    fs.sync_all().unwrap();
+    drop(fs);
+
+    let base = MmapBase::open(&path, &mut SimpleHasher).unwrap();
+    assert_eq!(base.len(), Some(2));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn chunk_iter_yields_file_contents() {
+    let data = (0..2 * MB + 1234)
+        .map(|i| (i % 241) as u8)
+        .collect::<Vec<_>>();
+    let mut fs = fs_with_file(&data);
+
+    let chunks = fs
+        .chunk_iter("file")
+        .unwrap()
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+    assert!(chunks.iter().all(|chunk| chunk.len() <= 4096));
+    assert_eq!(chunks.concat(), read_file(&fs, "file"));
+
+    let mut handle = fs.open_file("file", FSChunker::new(4096)).unwrap();
+    fs.write_sparse(&mut handle, 3 * MB, &[5; 100]).unwrap();
+    fs.close_file(handle).unwrap();
+
+    let contents = fs
+        .chunk_iter("file")
+        .unwrap()
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap()
+        .concat();
+    assert_eq!(contents.len(), 3 * MB + 100);
+    assert_eq!(contents, read_file(&fs, "file"));
+
+    assert_eq!(
+        fs.chunk_iter("missing").err().unwrap().kind(),
+        ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn rechunk_file_keeps_contents() {
+    let data = (0..2 * MB + 777)
+        .map(|i| ((i * 31 + i / 1000) % 251) as u8)
+        .collect::<Vec<_>>();
+    let mut fs = fs_with_file(&data);
+    let manifest = fs.file_manifest("file").unwrap();
+
+    fs.rechunk_file("file", SuperChunker::new()).unwrap();
+    assert_eq!(read_file(&fs, "file"), data);
+    assert_ne!(fs.file_manifest("file").unwrap(), manifest);
+    assert_eq!(fs.chunker_of("file").as_deref(), Some("SuperChunker"));
+    assert!(fs.file_is_complete("file").unwrap());
+
+    assert_eq!(
+        fs.rechunk_file("missing", SuperChunker::new())
+            .unwrap_err()
+            .kind(),
+        ErrorKind::NotFound
+    );
+    assert_eq!(fs.list_files_with_prefix(""), vec!["file".to_string()]);
+
+    let base = LimitedBase {
+        saves_left: 1,
+        ..Default::default()
+    };
+    let mut fs = FileSystem::new(base, SimpleHasher);
+    let handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_final(handle, &data[..MB]).unwrap();
+
+    let error = fs.rechunk_file("file", FSChunker::new(1000)).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+    assert_eq!(fs.list_files_with_prefix(""), vec!["file".to_string()]);
+    let handle = fs.open_file("file", FSChunker::new(4096)).unwrap();
+    assert_eq!(fs.read_file_complete(&handle).unwrap(), data[..MB]);
+}
+
+#[test]
+fn hash_collision_is_detected() {
+    let weak_hasher = || FnHasher::new(|data: &[u8]| vec![data.iter().fold(0u8, |a, b| a ^ b)]);
+
+    let mut fs = FileSystem::new(HashMapBase::with_collision_detection(), weak_hasher());
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4), true)
+        .unwrap();
+    fs.write_to_file(&mut handle, &[1, 2, 3, 4, 1, 2, 3, 4])
+        .unwrap();
+    let error = fs.write_to_file(&mut handle, &[4, 3, 2, 1]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    fs.close_file(handle).unwrap();
+    let handle = fs.open_file("file", FSChunker::new(4)).unwrap();
+    assert_eq!(
+        fs.read_file_complete(&handle).unwrap(),
+        vec![1, 2, 3, 4, 1, 2, 3, 4]
+    );
+
+    let mut fs = FileSystem::new(HashMapBase::default(), weak_hasher());
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4), true)
+        .unwrap();
+    fs.write_to_file(&mut handle, &[1, 2, 3, 4, 4, 3, 2, 1])
+        .unwrap();
+    fs.close_file(handle).unwrap();
+    let handle = fs.open_file("file", FSChunker::new(4)).unwrap();
+    assert_eq!(
+        fs.read_file_complete(&handle).unwrap(),
+        vec![1, 2, 3, 4, 1, 2, 3, 4]
+    );
+}
+
+#[test]
+fn interleaved_writes_to_two_files() {
+    let first_data = (0..3 * MB + 500)
+        .map(|i| (i % 239) as u8)
+        .collect::<Vec<_>>();
+    let second_data = (0..3 * MB + 1500)
+        .map(|i| (i % 227) as u8)
+        .collect::<Vec<_>>();
+
+    let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+    let mut first = fs
+        .create_file("first".to_string(), SuperChunker::new(), true)
+        .unwrap();
+    let mut second = fs
+        .create_file("second".to_string(), SuperChunker::new(), true)
+        .unwrap();
+
+    // segments of uneven sizes, so that both handles keep leftovers between writes
+    let mut first_parts = first_data.chunks(MB / 3);
+    let mut second_parts = second_data.chunks(MB / 2 + 7);
+    loop {
+        let first_part = first_parts.next();
+        let second_part = second_parts.next();
+        if first_part.is_none() && second_part.is_none() {
+            break;
+        }
+        if let Some(part) = first_part {
+            fs.write_to_file(&mut first, part).unwrap();
+        }
+        if let Some(part) = second_part {
+            fs.write_to_file(&mut second, part).unwrap();
+        }
+    }
+    fs.close_file(first).unwrap();
+    fs.close_file(second).unwrap();
+
+    let handle = fs.open_file("first", SuperChunker::new()).unwrap();
+    assert_eq!(fs.read_file_complete(&handle).unwrap(), first_data);
+    let handle = fs.open_file("second", SuperChunker::new()).unwrap();
+    assert_eq!(fs.read_file_complete(&handle).unwrap(), second_data);
+}
+
+#[test]
+fn snapshot_keeps_old_contents() {
+    let data = (0..MB + 300).map(|i| (i % 211) as u8).collect::<Vec<_>>();
+    let mut fs = fs_with_file(&data);
+    let chunk_count = fs.storage_iterator().count();
+
+    fs.snapshot("file", "snap").unwrap();
+    fs.copy_file("file", "copy").unwrap();
+    assert_eq!(fs.storage_iterator().count(), chunk_count);
+    assert_eq!(fs.list_snapshots(), vec!["snap".to_string()]);
+    assert_eq!(
+        fs.snapshot("file", "copy").unwrap_err().kind(),
+        ErrorKind::AlreadyExists
+    );
+
+    let mut handle = fs.open_file("file", FSChunker::new(4096)).unwrap();
+    fs.write_to_file(&mut handle, &[1; 5000]).unwrap();
+    fs.close_file(handle).unwrap();
+    fs.truncate_file("file", 100).unwrap();
+
+    assert_eq!(read_file(&fs, "snap"), data);
+    assert_eq!(read_file(&fs, "file"), data[..100]);
+}
+
+#[test]
+fn file_digest_compares_chunk_sequences() {
+    let mut data = (0..MB + 300).map(|i| (i % 211) as u8).collect::<Vec<_>>();
+    let mut fs = fs_with_file(&data);
+    fs.copy_file("file", "copy").unwrap();
+    assert_eq!(
+        fs.file_digest("file").unwrap(),
+        fs.file_digest("copy").unwrap()
+    );
+
+    data[5000] ^= 1;
+    let mut handle = fs
+        .create_file("changed".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_to_file(&mut handle, &data).unwrap();
+    fs.close_file(handle).unwrap();
+    assert_ne!(
+        fs.file_digest("file").unwrap(),
+        fs.file_digest("changed").unwrap()
+    );
+
+    assert_eq!(
+        fs.file_digest("missing").unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+
+    // the encoding is fixed: a tag, the length of the hash and the hash itself for every chunk
+    let fs = fs_with_file(&[7; 4096]);
+    let mut encoded = vec![0];
+    encoded.extend(4096u64.to_le_bytes());
+    encoded.extend([7; 4096]);
+    assert_eq!(
+        fs.file_digest("file").unwrap().as_slice(),
+        Sha256Hasher::default().hash(&encoded).as_slice()
+    );
+}
+
+#[test]
+fn create_or_open_appends_to_existing_file() {
+    let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
+
+    let mut handle = fs
+        .create_or_open("file".to_string(), FSChunker::new(4096))
+        .unwrap();
+    fs.write_to_file(&mut handle, &[1; 10000]).unwrap();
+    fs.close_file(handle).unwrap();
+
+    let mut handle = fs
+        .create_or_open("file".to_string(), FSChunker::new(4096))
+        .unwrap();
+    fs.write_to_file(&mut handle, &[2; 5000]).unwrap();
+    fs.close_file(handle).unwrap();
+
+    let mut expected = vec![1; 10000];
+    expected.extend([2; 5000]);
+    assert_eq!(read_file(&fs, "file"), expected);
+    assert_eq!(fs.list_files_with_prefix(""), vec!["file".to_string()]);
+
+    let handle = fs
+        .create_file_encrypted(
+            "encrypted".to_string(),
+            FSChunker::new(4096),
+            FileKey::new([1; 32]),
+            true,
+        )
+        .unwrap();
+    fs.close_file(handle).unwrap();
+    assert_eq!(
+        fs.create_or_open("encrypted".to_string(), FSChunker::new(4096))
+            .unwrap_err()
+            .kind(),
+        ErrorKind::PermissionDenied
+    );
+}
+
+#[test]
+fn failed_close_does_not_panic_on_drop() {
+    let base = LimitedBase {
+        saves_left: 1,
+        ..Default::default()
+    };
+    let mut fs = FileSystem::new(base, SimpleHasher);
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_to_file(&mut handle, &[1; 1000]).unwrap();
+    assert!(handle.has_unflushed_data());
+    let error = fs.write_final(handle, &[2; 100]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+
+    let base = LimitedBase {
+        saves_left: 1,
+        ..Default::default()
+    };
+    let mut fs = FileSystem::new(base, SimpleHasher);
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    fs.write_to_file(&mut handle, &[1; 1000]).unwrap();
+    let error = fs.close_file(handle).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+    assert_eq!(fs.open_handles(), 0);
+}
+
+#[test]
+fn stream_write_stops_after_storage_failure() {
+    let base = LimitedBase {
+        saves_left: 2,
+        ..Default::default()
+    };
+    let mut fs = FileSystem::new(base, SimpleHasher);
+    let data = (0..5 * MB).map(|i| (i % 241) as u8).collect::<Vec<_>>();
+
+    let mut handle = fs
+        .create_file("file".to_string(), FSChunker::new(4096), true)
+        .unwrap();
+    let written = fs
+        .write_from_stream_buffered(&mut handle, data.as_slice(), 3 * MB)
+        .unwrap();
+    assert_eq!(written, 2 * MB);
+    assert_eq!(
+        fs.list_files_with_sizes(),
+        vec![("file".to_string(), 2 * MB as u64)]
+    );
+
+    let result = fs.write_from_stream(&mut handle, data.as_slice());
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::OutOfMemory);
+    fs.close_file(handle).unwrap();
+
+    let handle = fs.open_file("file", FSChunker::new(4096)).unwrap();
+    assert_eq!(fs.read_file_complete(&handle).unwrap(), data[..2 * MB]);
 }
 
 //#[test]
+#[allow(dead_code)]
 fn two_file_handles_to_one_file() {
     let mut fs = FileSystem::new(HashMapBase::default(), SimpleHasher);
     let mut handle1 = fs